@@ -1,25 +1,120 @@
 use super::backup_writer::write_files_with_wd;
+use super::encryption::{BackupFile, MasterKey};
+use super::BackupChain;
 use crate::backup::backup::backup_reader::BackupReader;
 use crate::backup::backup::backup_writer::BackupWriter;
-use crate::backup::BackupArgs;
+use crate::backup::backup::manifest::{Manifest, MANIFEST_FILE};
+use crate::backup::{BackupArgs, BackupType, CompressionPolicy, Storage};
+use crate::region::{compose_deltas, RegionDeltaReader};
 use crate::try_option;
-use crate::utils::BackupsFolder;
+use crate::utils::{BackupsFolder, TempFile};
 use anyhow::anyhow;
 use anyhow::Result;
+use chrono::{DateTime, Local, TimeZone, Utc};
 use quartz_nbt::io::Flavor;
 use quartz_nbt::serde::{deserialize, serialize};
 use serde::Deserialize;
 use serde::Serialize;
-use std::io::Read;
+use std::collections::HashSet;
+use std::ffi::OsStr;
+use std::fs;
+use std::fs::File;
+use std::io::{Read, Write};
 use std::path::Path;
 use std::path::PathBuf;
+use zip::ZipWriter;
 
-pub const PREV_BACKUP_PREFIX: &'static str = "__in_prev_backup_";
+pub const PREV_BACKUP_PREFIX: &str = "__in_prev_backup_";
+pub const CHANGED_CHUNKS_PREFIX: &str = "__changed_chunks_";
+pub const ARCHIVE_DATA_FILE: &str = "archive_data.nbt";
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+/// Whether the file is one of the files the backup manager stores in each backup rather than a file from the world
+pub fn is_metadata_file(name: &str) -> bool {
+    name == ARCHIVE_DATA_FILE || name == MANIFEST_FILE
+}
+
+pub fn is_prev_backup_marker(name: &str) -> bool {
+    prev_backup_marker_target(name).is_some()
+}
+
+/// The name of the file indicating that `path` is unchanged since the previous backup
+pub fn prev_backup_marker(path: impl AsRef<Path>) -> PathBuf {
+    let path = path.as_ref();
+
+    path.with_file_name(
+        PREV_BACKUP_PREFIX.to_owned() + &path.file_name().unwrap().to_string_lossy(),
+    )
+}
+
+/// The path of the file that a marker created by `prev_backup_marker` stands in for
+pub fn prev_backup_marker_target(name: &str) -> Option<PathBuf> {
+    let path = Path::new(name);
+    let file_name = path.file_name()?.to_str()?;
+
+    Some(path.with_file_name(file_name.strip_prefix(PREV_BACKUP_PREFIX)?))
+}
+
+/// The name of the file holding the chunks of the region file at `path` that changed since the previous backup
+pub fn changed_chunks_entry(path: impl AsRef<Path>) -> PathBuf {
+    let path = path.as_ref();
+
+    path.with_file_name(
+        CHANGED_CHUNKS_PREFIX.to_owned() + &path.file_name().unwrap().to_string_lossy(),
+    )
+}
+
+/// The path of the region file that an entry created by `changed_chunks_entry` holds the changes to
+pub fn changed_chunks_target(name: &str) -> Option<PathBuf> {
+    let path = Path::new(name);
+    let file_name = path.file_name()?.to_str()?;
+
+    Some(path.with_file_name(file_name.strip_prefix(CHANGED_CHUNKS_PREFIX)?))
+}
+
+/// The contents of `archive_data.nbt`, every field except `previous` and `current` is missing from backups made by older versions
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
 pub struct BackupData {
     pub previous: Option<PathBuf>,
     pub current: PathBuf,
+    /// Unix timestamp in milliseconds of when the backup was made
+    pub created: Option<i64>,
+    pub backup_type: Option<String>,
+    pub tool_version: Option<String>,
+    pub host_name: Option<String>,
+    pub source: Option<PathBuf>,
+    pub description: Option<String>,
+    #[serde(default)]
+    pub tags: Vec<String>,
+    pub file_count: Option<i64>,
+    pub byte_count: Option<i64>,
+    /// `chunks` if the files are in the chunk store rather than in the backup itself
+    pub storage: Option<String>,
+    /// How files are compressed unless an override matches them, like `zstd-19`
+    pub compression: Option<String>,
+    /// Patterns of files that are compressed differently, like `*.mca=stored`
+    #[serde(default)]
+    pub compression_overrides: Vec<String>,
+    /// The gitignore lines that decided which files were left out, so restores leave those files alone
+    #[serde(default)]
+    pub ignore_patterns: Vec<String>,
+}
+
+impl BackupData {
+    pub fn is_chunked(&self) -> bool {
+        self.storage.as_deref() == Some(Storage::Chunks.as_str())
+    }
+
+    pub fn is_full(&self) -> bool {
+        self.backup_type.as_deref() == Some(BackupType::Full.as_str())
+    }
+
+    pub fn to_bytes(&self) -> Result<Vec<u8>> {
+        Ok(serialize(self, Some(""), Flavor::Uncompressed)?)
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<BackupData> {
+        Ok(deserialize::<BackupData>(bytes, Flavor::Uncompressed)?.0)
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -29,46 +124,177 @@ pub struct Backup {
 
 impl Backup {
     pub(super) fn new(data: BackupData) -> Backup {
-        Backup { data: data }
+        Backup { data }
     }
 
     pub fn get(path: impl AsRef<Path>) -> Result<Option<Backup>> {
         let mut reader = try_option!(BackupReader::new(&path));
 
-        let mut file = reader.get_file("archive_data.nbt").ok_or(anyhow!("The file archive_data.nbt is missing from the backup `{}`, this file contains which backup comes before it, which is important for incremental backups", path.as_ref().file_name().unwrap().to_string_lossy()))?;
+        let mut file = reader.get_file(ARCHIVE_DATA_FILE).map_err(|_| anyhow!("The file {} is missing from the backup `{}`, this file contains which backup comes before it, which is important for incremental backups", ARCHIVE_DATA_FILE, path.as_ref().file_name().unwrap().to_string_lossy()))?;
 
         let mut data_buf = Vec::new();
         file.read_to_end(&mut data_buf)?;
 
-        let archive_data = deserialize::<BackupData>(&data_buf, Flavor::Uncompressed)?;
+        let data = BackupData::from_bytes(&data_buf)?;
+
+        // Backups record absolute paths, but every backup of a repository is in the same folder, so the repository can be moved or mounted somewhere else
+        let dir = path.as_ref().parent().unwrap_or_else(|| Path::new(""));
+        let rebase = |v: &Path| match v.file_name() {
+            Some(name) => dir.join(name),
+            None => v.to_path_buf(),
+        };
 
         Ok(Some(Backup {
-            data: archive_data.0,
+            data: BackupData {
+                current: rebase(&data.current),
+                previous: data.previous.as_deref().map(rebase),
+                ..data
+            },
         }))
     }
 
-    pub fn create(from: &PathBuf, backups_dir: BackupsFolder, args: &BackupArgs) -> Result<Backup> {
-        let prev = backups_dir.current_backup()?;
+    /// Every backup in the folder that can be read, the ones that can't are skipped with a warning
+    pub fn all_readable(backups_dir: &BackupsFolder) -> Result<Vec<Backup>> {
+        let mut backups = Vec::new();
+
+        for file in backups_dir.all_backups()? {
+            match Backup::get(file.path()) {
+                Ok(v) => backups.extend(v),
+                Err(e) => println!(
+                    "Skipping `{}`, it can't be read: {:#}",
+                    file.file_name().to_string_lossy(),
+                    e
+                ),
+            }
+        }
+
+        Ok(backups)
+    }
+
+    pub fn create(from: &Path, backups_dir: BackupsFolder, args: &BackupArgs) -> Result<Backup> {
+        // Garbage collection would delete chunks that have been stored but that no manifest refers to yet
+        let _lock = backups_dir.lock()?;
+
+        let key = MasterKey::for_repository(backups_dir.dir())?;
+
+        let current = match backups_dir.current_backup()? {
+            // Taking files from a backup that's encrypted differently would leave them unencrypted or unreadable.
+            // Only the header is checked, since opening it would fail without the key or with one
+            Some(v) if v.exists() && BackupFile::is_encrypted(&v)? != key.is_some() => {
+                println!(
+                    "The previous backup is {}, so the new backup won't build upon it",
+                    if key.is_some() {
+                        "not encrypted"
+                    } else {
+                        "encrypted"
+                    }
+                );
+
+                None
+            }
+            Some(v) => Backup::get(v)?,
+            None => None,
+        };
+
+        // Chunked backups never build upon other backups, and zip backups can't build upon chunked ones.
+        // `reference` is the backup that unchanged files can be taken from
+        let (prev, reference, backup_type) = match args.storage {
+            Storage::Zip => match args.backup_type {
+                // A full backup starts a new chain
+                BackupType::Full => (None, None, BackupType::Full),
+                BackupType::Partial => {
+                    let prev = current.filter(|v| !v.get_data().is_chunked());
+
+                    // Without a previous backup every file is stored, which makes it a full backup
+                    let backup_type = match prev {
+                        Some(_) => BackupType::Partial,
+                        None => BackupType::Full,
+                    };
+
+                    (prev.clone(), prev, backup_type)
+                }
+            },
+            Storage::Chunks => (
+                None,
+                current.filter(|v| v.get_data().is_chunked()),
+                BackupType::Full,
+            ),
+        };
 
         let data = BackupData {
-            previous: prev,
+            previous: prev.map(|v| v.get_data().current.clone()),
             current: backups_dir.dir().join(&args.name),
+            created: Some(Utc::now().timestamp_millis()),
+            backup_type: Some(backup_type.as_str().to_string()),
+            tool_version: Some(crate_version!().to_string()),
+            host_name: hostname::get()
+                .ok()
+                .map(|v| v.to_string_lossy().to_string()),
+            source: Some(from.to_path_buf()),
+            description: args.description.clone(),
+            tags: args.tags.clone(),
+            storage: Some(args.storage.as_str().to_string()),
+            compression: Some(args.compression.default.to_string()),
+            compression_overrides: args.compression.overrides(),
+            ignore_patterns: args.ignore.clone(),
+            ..BackupData::default()
         };
 
-        let mut backup_writer = BackupWriter::new(&from, data.clone(), &args)?;
+        let write = || -> Result<BackupData> {
+            let mut backup_writer = BackupWriter::new(&from, data.clone(), args, reference, key)?;
 
-        backups_dir.set_current_backup(&args.name)?;
+            write_files_with_wd(&mut backup_writer, &from)?;
+
+            let manifest = backup_writer.manifest();
+            let data = BackupData {
+                file_count: Some(manifest.files.len() as i64),
+                byte_count: Some(manifest.files.iter().map(|v| v.size).sum()),
+                ..data.clone()
+            };
+
+            backup_writer.add_new_file(
+                &from.join(MANIFEST_FILE),
+                (&mut &backup_writer.manifest().to_bytes()?[..]) as &mut dyn Read,
+            )?;
+
+            backup_writer.add_new_file(
+                &from.join(ARCHIVE_DATA_FILE),
+                (&mut &data.to_bytes()?[..]) as &mut dyn Read,
+            )?;
+
+            backup_writer.finish()?;
 
-        write_files_with_wd(&mut backup_writer, from)?;
+            Ok(data)
+        };
 
-        backup_writer.add_new_file(
-            &from.join("archive_data.nbt"),
-            (&mut &serialize(&data, Some(""), Flavor::Uncompressed)?[..]) as &mut dyn Read,
-        )?;
+        // `.current` only names finished backups, a half written one would break every backup after it
+        let data = match write() {
+            Ok(v) => v,
+            Err(e) => {
+                let _ = fs::remove_file(&data.current);
+                return Err(e);
+            }
+        };
+
+        backups_dir.set_current_backup(&args.name)?;
 
         Ok(Backup::new(data))
     }
 
+    /// The hashes of every file in the backup, backups made by older versions don't have one
+    pub fn manifest(&self) -> Result<Option<Manifest>> {
+        let mut reader = self.get_reader()?;
+
+        if !reader.file_names().any(|v| v == MANIFEST_FILE) {
+            return Ok(None);
+        }
+
+        let mut data_buf = Vec::new();
+        reader.get_file(MANIFEST_FILE)?.read_to_end(&mut data_buf)?;
+
+        Ok(Some(Manifest::from_bytes(&data_buf)?))
+    }
+
     pub fn get_reader(&self) -> Result<BackupReader> {
         BackupReader::new(&self.get_data().current)?
             .ok_or(anyhow!("The backup `{}` doesn't exist", self.get_name()))
@@ -91,7 +317,8 @@ impl Backup {
             file_path.as_ref().as_os_str().to_string_lossy()
         ))?;
 
-        let indication_string = &(PREV_BACKUP_PREFIX.to_owned() + path_string);
+        let marker = prev_backup_marker(path_string);
+        let indication_string = marker.to_str().unwrap();
 
         if reader.file_names().any(|v| v == indication_string) {
             let prev = self.prev()?.ok_or(anyhow!("The backup `{}` doesn't indicate a previous backup, but includes a file referencing a previous backup, `{}`", self.get_name(), path_string))?;
@@ -103,6 +330,246 @@ impl Backup {
         Ok(None)
     }
 
+    /// Copies the files that `child` takes from this backup into `child`, so this backup can be deleted without breaking it
+    pub fn fold_into(&self, child: &Backup) -> Result<()> {
+        let mut own_reader = self.get_reader()?;
+        let mut child_reader = child.get_reader()?;
+
+        let own_names = own_reader
+            .file_names()
+            .map(|v| v.to_string())
+            .collect::<HashSet<_>>();
+        let child_names = child_reader
+            .entries()?
+            .into_iter()
+            .map(|v| v.name)
+            .collect::<Vec<_>>();
+
+        let compression = CompressionPolicy::from_data(child.get_data())?;
+        let child_chain = BackupChain::new(child.clone())?;
+
+        let child_path = &child.get_data().current;
+        let temp_path = child_path.with_extension("zip.tmp");
+        let mut out = ZipWriter::new(BackupFile::create(&temp_path, child_reader.master_key())?);
+
+        for name in child_names {
+            if name == ARCHIVE_DATA_FILE {
+                continue;
+            }
+
+            if let Some(target) = prev_backup_marker_target(&name) {
+                let target = target.to_string_lossy();
+
+                if own_names.contains(&target[..]) {
+                    out.raw_copy_file(own_reader.get_file(&target)?)?;
+                    continue;
+                }
+
+                // The changed chunks still build upon the same backups once this one is gone
+                let changed_chunks = changed_chunks_entry(&target[..]);
+                let changed_chunks = changed_chunks.to_string_lossy();
+
+                if own_names.contains(&changed_chunks[..]) {
+                    out.raw_copy_file(own_reader.get_file(&changed_chunks)?)?;
+                    continue;
+                }
+            }
+
+            // Regions are rebuilt one chunk at a time, so that large regions never have to be in memory
+            if let Some(target) = changed_chunks_target(&name) {
+                let target = target.to_string_lossy();
+                let own_changed_chunks = changed_chunks_entry(&target[..]);
+                let own_changed_chunks = own_changed_chunks.to_string_lossy();
+
+                if own_names.contains(&target[..]) {
+                    let mut region = child_chain.open_region(&target, 0)?.ok_or(anyhow!(
+                        "The region file `{}` disappeared from the backups",
+                        target
+                    ))?;
+
+                    out.start_file(&target[..], compression.options_for(&target[..]))?;
+                    region.write_to(&mut out)?;
+                    continue;
+                }
+
+                if own_names.contains(&own_changed_chunks[..]) {
+                    let mut own = RegionDeltaReader::new(TempFile::copy_from(
+                        &mut child_reader.get_file(&name)?,
+                    )?)?;
+                    let mut previous = RegionDeltaReader::new(TempFile::copy_from(
+                        &mut own_reader.get_file(&own_changed_chunks)?,
+                    )?)?;
+
+                    out.start_file(&name, compression.options_for(&name))?;
+                    compose_deltas(&mut out, &mut own, &mut previous)?;
+                    continue;
+                }
+            }
+
+            out.raw_copy_file(child_reader.get_file(&name)?)?;
+        }
+
+        let data = BackupData {
+            previous: self.get_data().previous.clone(),
+            ..child.get_data().clone()
+        };
+
+        out.start_file(ARCHIVE_DATA_FILE, compression.default.options())?;
+        out.write_all(&data.to_bytes()?)?;
+        out.finish()?.flush()?;
+
+        drop(child_reader);
+
+        replace_backup_file(&temp_path, child_path)
+    }
+
+    /// Rewrites this backup so it holds every file itself instead of taking files from previous backups
+    pub fn consolidate(&self) -> Result<()> {
+        if self.get_data().is_chunked() {
+            return Err(anyhow!(
+                "The backup `{}` is a chunked backup, which never depends on other backups",
+                self.get_name()
+            ));
+        }
+
+        let chain = BackupChain::new(self.clone())?;
+        chain.check_complete(&|_| true)?;
+
+        let mut readers = chain
+            .links()
+            .iter()
+            .map(|v| v.backup.get_reader())
+            .collect::<Result<Vec<_>>>()?;
+
+        let entries = readers[0].entries()?;
+        let compression = CompressionPolicy::from_data(self.get_data())?;
+
+        let path = &self.get_data().current;
+        let temp_path = path.with_extension("zip.tmp");
+        let mut out = ZipWriter::new(BackupFile::create(&temp_path, readers[0].master_key())?);
+
+        for entry in entries {
+            if entry.name == ARCHIVE_DATA_FILE {
+                continue;
+            }
+
+            if entry.is_dir || is_metadata_file(&entry.name) {
+                out.raw_copy_file(readers[0].get_file(&entry.name)?)?;
+                continue;
+            }
+
+            let file_path = prev_backup_marker_target(&entry.name)
+                .or_else(|| changed_chunks_target(&entry.name))
+                .map(|v| v.to_string_lossy().to_string())
+                .unwrap_or(entry.name);
+
+            let (link, stored) = chain.resolve(&file_path, 0).unwrap();
+
+            if stored.name == file_path {
+                out.raw_copy_file_rename(readers[link].get_file(&stored.name)?, &file_path)?;
+            } else {
+                out.start_file(&file_path, compression.options_for(&file_path))?;
+                chain
+                    .open_region(&file_path, link)?
+                    .unwrap()
+                    .write_to(&mut out)?;
+            }
+        }
+
+        let data = BackupData {
+            previous: None,
+            backup_type: Some(BackupType::Full.as_str().to_string()),
+            ..self.get_data().clone()
+        };
+
+        out.start_file(ARCHIVE_DATA_FILE, compression.default.options())?;
+        out.write_all(&data.to_bytes()?)?;
+        out.finish()?.flush()?;
+
+        drop(readers);
+
+        replace_backup_file(&temp_path, path)
+    }
+
+    /// Deletes this backup, folding it into the backups that depend on it if `fold` is set
+    pub fn delete(&self, backups_dir: &BackupsFolder, fold: bool) -> Result<()> {
+        let name = self.get_name();
+        let dependents = dependents_of(backups_dir, &name)?;
+
+        if !dependents.is_empty() {
+            if !fold {
+                return Err(anyhow!(
+                    "The backup `{}` can't be deleted because these backups depend on it: {}\nUse --fold to move the files they need into them first",
+                    name,
+                    names(&dependents)
+                ));
+            }
+
+            for dependent in &dependents {
+                println!("Folding `{}` into `{}`", name, dependent.get_name());
+
+                self.fold_into(dependent)?;
+            }
+        }
+
+        println!("Deleting `{}`", name);
+
+        let was_current = is_current(backups_dir, &name)?;
+
+        fs::remove_file(&self.get_data().current)?;
+
+        if self.get_data().is_chunked() {
+            println!("Run `gc` to free the space taken by chunks that no other backup uses");
+        }
+
+        // `.current` only moves back to the previous backup if it named this one
+        if was_current {
+            let previous = self.prev()?.map(|v| v.get_name()).unwrap_or_default();
+
+            backups_dir.set_current_backup(&previous)?;
+        }
+
+        Ok(())
+    }
+
+    /// Deletes a backup file that can't be read, which can't be folded into the backups that depend on it
+    pub fn delete_unreadable(path: &Path, backups_dir: &BackupsFolder) -> Result<()> {
+        let name = path.file_name().unwrap().to_string_lossy().to_string();
+        let dependents = dependents_of(backups_dir, &name)?;
+
+        if !dependents.is_empty() {
+            return Err(anyhow!(
+                "The backup `{}` can't be deleted because these backups depend on it: {}\nIt can't be read, so it can't be folded into them",
+                name,
+                names(&dependents)
+            ));
+        }
+
+        println!("Deleting `{}`", name);
+
+        let was_current = is_current(backups_dir, &name)?;
+
+        fs::remove_file(path)?;
+
+        // There's no telling which backup came before this one, so the next backup won't build upon any
+        if was_current {
+            backups_dir.set_current_backup("")?;
+        }
+
+        Ok(())
+    }
+
+    /// When the backup was made, backups from older versions don't record this so the time the file was last modified is used instead
+    pub fn created(&self) -> Result<DateTime<Local>> {
+        if let Some(created) = self.get_data().created {
+            return Ok(Local.timestamp_millis(created));
+        }
+
+        Ok(DateTime::from(
+            fs::metadata(&self.get_data().current)?.modified()?,
+        ))
+    }
+
     pub fn get_data(&self) -> &BackupData {
         &self.data
     }
@@ -111,6 +578,21 @@ impl Backup {
         Backup::get(try_option!(no_try, &self.data.previous))
     }
 
+    pub fn previous_name(&self) -> Option<String> {
+        self.get_data()
+            .previous
+            .as_ref()
+            .map(|v| v.file_name().unwrap().to_string_lossy().to_string())
+    }
+
+    /// The name of the backup that this backup takes files from, full backups made by older versions still record the backup before them but don't need it
+    pub fn depends_on(&self) -> Option<String> {
+        match self.get_data().is_full() || self.get_data().is_chunked() {
+            true => None,
+            false => self.previous_name(),
+        }
+    }
+
     pub fn get_name(&self) -> String {
         self.get_data()
             .current
@@ -120,3 +602,39 @@ impl Backup {
             .to_string()
     }
 }
+
+/// Moves a rewritten backup over the original, keeping the modification time since it's used as the time the backup was made
+fn replace_backup_file(temp_path: &Path, path: &Path) -> Result<()> {
+    let created = fs::metadata(path)?.modified()?;
+    fs::rename(temp_path, path)?;
+    File::options()
+        .write(true)
+        .open(path)?
+        .set_modified(created)?;
+
+    Ok(())
+}
+
+/// The readable backups that build upon the backup called `name`
+fn dependents_of(backups_dir: &BackupsFolder, name: &str) -> Result<Vec<Backup>> {
+    Ok(Backup::all_readable(backups_dir)?
+        .into_iter()
+        .filter(|v| v.depends_on().as_deref() == Some(name))
+        .collect())
+}
+
+fn names(backups: &[Backup]) -> String {
+    backups
+        .iter()
+        .map(|v| v.get_name())
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// Whether `.current` names the backup called `name`
+fn is_current(backups_dir: &BackupsFolder, name: &str) -> Result<bool> {
+    Ok(backups_dir
+        .current_backup()?
+        .map(|v| v.file_name() == Some(OsStr::new(name)))
+        .unwrap_or(false))
+}
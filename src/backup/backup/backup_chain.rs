@@ -0,0 +1,337 @@
+use anyhow::{anyhow, Result};
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::fs::File;
+use std::io;
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+use super::backup_reader::BackupEntry;
+use super::manifest::Manifest;
+use super::ChunkStore;
+use super::{
+    changed_chunks_entry, changed_chunks_target, is_metadata_file, prev_backup_marker,
+    prev_backup_marker_target, Backup,
+};
+use crate::region::{stream_region, Chunk, RegionDeltaReader, RegionReader};
+use crate::utils::TempFile;
+
+pub struct ChainLink {
+    pub backup: Backup,
+    pub entries: HashMap<String, BackupEntry>,
+}
+
+/// A backup along with every backup it builds upon, most recent first
+pub struct BackupChain {
+    links: Vec<ChainLink>,
+    missing: Option<PathBuf>,
+    /// The files of a chunked backup, which are in the chunk store rather than in the backup itself
+    chunked: Option<(ChunkStore, Manifest)>,
+}
+
+/// Where the contents of a file in a backup are actually stored
+pub struct ResolvedFile<'a> {
+    pub path: String,
+    pub location: Option<(usize, &'a BackupEntry)>,
+}
+
+impl BackupChain {
+    pub fn new(backup: Backup) -> Result<BackupChain> {
+        let mut links = Vec::new();
+        let mut seen = HashSet::new();
+        let mut missing = None;
+        let chunked = match backup.get_data().is_chunked() {
+            true => Some((
+                ChunkStore::new(backup.get_data().current.parent().unwrap()),
+                backup.manifest()?.ok_or(anyhow!(
+                    "The chunked backup `{}` doesn't have a manifest",
+                    backup.get_name()
+                ))?,
+            )),
+            false => None,
+        };
+
+        let mut next = Some(backup);
+
+        while let Some(backup) = next {
+            if !seen.insert(backup.get_data().current.clone()) {
+                break;
+            }
+
+            let entries = backup
+                .get_reader()?
+                .entries()?
+                .into_iter()
+                .map(|v| (v.name.clone(), v))
+                .collect();
+
+            // Full backups hold every file themselves, even if they record the backup before them
+            next = match backup.depends_on() {
+                Some(_) => backup.prev()?,
+                None => None,
+            };
+
+            if next.is_none() && backup.depends_on().is_some() {
+                missing = backup.get_data().previous.clone();
+            }
+
+            links.push(ChainLink { backup, entries });
+        }
+
+        Ok(BackupChain {
+            links,
+            missing,
+            chunked,
+        })
+    }
+
+    pub fn links(&self) -> &[ChainLink] {
+        &self.links
+    }
+
+    pub fn head(&self) -> &ChainLink {
+        &self.links[0]
+    }
+
+    /// The chunk store and manifest if the backup is a chunked backup
+    pub fn chunked(&self) -> Option<&(ChunkStore, Manifest)> {
+        self.chunked.as_ref()
+    }
+
+    /// The first ancestor that the chain refers to but that doesn't exist
+    pub fn missing(&self) -> Option<&PathBuf> {
+        self.missing.as_ref()
+    }
+
+    /// Finds the backup in the chain that holds the contents of `path`, starting at the link `from`
+    ///
+    /// For region files this may be an entry holding only the chunks that changed, in which case the rest of the region can also be found further along the chain
+    pub fn resolve(&self, path: impl AsRef<Path>, from: usize) -> Option<(usize, &BackupEntry)> {
+        let path = path.as_ref().to_str()?;
+        let marker = prev_backup_marker(path);
+        let marker = marker.to_str()?;
+        let changed_chunks = changed_chunks_entry(path);
+        let changed_chunks = changed_chunks.to_str()?;
+
+        // The first backup holding only the changed chunks, which is only usable if a backup further along has the whole region
+        let mut changes = None;
+
+        for (i, link) in self.links.iter().enumerate().skip(from) {
+            if let Some(entry) = link.entries.get(path) {
+                return Some(changes.unwrap_or((i, entry)));
+            }
+
+            if let Some(entry) = link.entries.get(changed_chunks) {
+                changes.get_or_insert((i, entry));
+                continue;
+            }
+
+            if !link.entries.contains_key(marker) {
+                return None;
+            }
+        }
+
+        None
+    }
+
+    /// Every file in the most recent backup of the chain, with markers replaced by the files they stand in for
+    pub fn files(&self) -> Vec<ResolvedFile<'_>> {
+        let mut files = self
+            .head()
+            .entries
+            .values()
+            .filter(|v| !v.is_dir && !is_metadata_file(&v.name))
+            .map(|v| {
+                let path = match prev_backup_marker_target(&v.name)
+                    .or_else(|| changed_chunks_target(&v.name))
+                {
+                    Some(target) => target.to_string_lossy().to_string(),
+                    None => v.name.clone(),
+                };
+
+                ResolvedFile {
+                    location: self.resolve(&path, 0),
+                    path,
+                }
+            })
+            .collect::<Vec<_>>();
+
+        files.sort_by(|a, b| a.path.cmp(&b.path));
+
+        files
+    }
+
+    /// The region file at `path` as of the link `from`, returning `None` if the chain doesn't have it
+    ///
+    /// The entries the region is made up of are copied out of the backups so that its chunks can be read one at a time
+    pub fn open_region(&self, path: &str, from: usize) -> Result<Option<ChainRegion>> {
+        let mut changes = Vec::new();
+        let mut at = from;
+
+        loop {
+            let (link, entry) = match self.resolve(path, at) {
+                Some(v) => v,
+                None if changes.is_empty() => return Ok(None),
+                None => {
+                    return Err(anyhow!(
+                        "The previous version of the region file `{}` is missing",
+                        path
+                    ))
+                }
+            };
+
+            let file = TempFile::copy_from(
+                &mut self.links[link]
+                    .backup
+                    .get_reader()?
+                    .get_file(&entry.name)?,
+            )?;
+
+            if entry.name == path {
+                return Ok(Some(ChainRegion {
+                    changes,
+                    base: RegionReader::new(file)?,
+                }));
+            }
+
+            changes.push(RegionDeltaReader::new(file)?);
+            at = link + 1;
+        }
+    }
+
+    /// Makes sure that every included file stored in a previous backup can be found
+    pub fn check_complete(&self, include: &dyn Fn(&str) -> bool) -> Result<()> {
+        if let Some((store, manifest)) = &self.chunked {
+            for entry in manifest.files.iter().filter(|v| include(&v.path)) {
+                if let Some(chunk) = entry.chunks.iter().find(|v| !store.contains(v)) {
+                    return Err(anyhow!(
+                        "`{}` is made up of the chunk `{}`, which is missing from the chunk store",
+                        entry.path,
+                        chunk
+                    ));
+                }
+            }
+
+            return Ok(());
+        }
+
+        if let Some(file) = self
+            .files()
+            .iter()
+            .find(|v| v.location.is_none() && include(&v.path))
+        {
+            return Err(match self.missing() {
+                Some(missing) => anyhow!(
+                    "`{}` is stored in the previous backup `{}`, which is missing",
+                    file.path,
+                    missing.display()
+                ),
+                None => anyhow!(
+                    "`{}` is supposed to be stored in a previous backup, but none of them contain it",
+                    file.path
+                ),
+            });
+        }
+
+        Ok(())
+    }
+
+    /// Writes every included file in the most recent backup of the chain to `dir`, taking files that are stored in previous backups from them
+    pub fn extract_to(&self, dir: &Path, include: &dyn Fn(&str) -> bool) -> Result<()> {
+        self.check_complete(include)?;
+
+        let mut files = self.files();
+        files.retain(|v| include(&v.path));
+
+        fs::create_dir_all(dir)?;
+
+        for entry in self
+            .head()
+            .entries
+            .values()
+            .filter(|v| v.is_dir && include(&v.name))
+        {
+            fs::create_dir_all(dir.join(&entry.name))?;
+        }
+
+        if let Some((store, manifest)) = &self.chunked {
+            for entry in manifest.files.iter().filter(|v| include(&v.path)) {
+                let path = dir.join(&entry.path);
+                fs::create_dir_all(path.parent().unwrap())?;
+
+                io::copy(&mut store.open(&entry.chunks), &mut File::create(path)?)?;
+            }
+
+            return Ok(());
+        }
+
+        let mut readers = self.links.iter().map(|_| None).collect::<Vec<_>>();
+
+        for file in files {
+            let (link, entry) = file.location.unwrap();
+
+            let path = dir.join(&file.path);
+            fs::create_dir_all(path.parent().unwrap())?;
+
+            if entry.name != file.path {
+                self.open_region(&file.path, link)?
+                    .unwrap()
+                    .write_to(&mut BufWriter::new(File::create(path)?))?;
+
+                continue;
+            }
+
+            if readers[link].is_none() {
+                readers[link] = Some(self.links[link].backup.get_reader()?);
+            }
+
+            let mut data = readers[link].as_mut().unwrap().get_file(&entry.name)?;
+
+            io::copy(&mut data, &mut File::create(path)?)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// A region file rebuilt from the backups in a chain, read one chunk at a time
+pub struct ChainRegion {
+    /// The chunks that changed in each version, most recent first
+    changes: Vec<RegionDeltaReader<TempFile>>,
+    /// The oldest version, which has the whole region
+    base: RegionReader<TempFile>,
+}
+
+impl ChainRegion {
+    pub fn read_chunk(&mut self, index: usize) -> Result<Option<Chunk>> {
+        let unchanged_missing = || {
+            anyhow!(
+                "Chunk {} is supposed to be unchanged, but the previous version of the region doesn't have it",
+                index
+            )
+        };
+
+        for (i, changes) in self.changes.iter_mut().enumerate() {
+            if !changes.is_present(index) {
+                return match i {
+                    0 => Ok(None),
+                    _ => Err(unchanged_missing()),
+                };
+            }
+
+            if let Some(chunk) = changes.read_chunk(index)? {
+                return Ok(Some(chunk));
+            }
+        }
+
+        match self.base.chunk(index)? {
+            None if !self.changes.is_empty() => Err(unchanged_missing()),
+            chunk => Ok(chunk),
+        }
+    }
+
+    /// Writes the region file the same way `write_region` does
+    pub fn write_to(&mut self, out: &mut dyn Write) -> Result<()> {
+        stream_region(out, &mut |index| self.read_chunk(index))
+    }
+}
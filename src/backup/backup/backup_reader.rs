@@ -1,36 +1,83 @@
-use anyhow::Result;
-use std::fs::File;
+use anyhow::{anyhow, Error, Result};
+use std::io;
 use std::path::Path;
 use zip::read::ZipFile;
 use zip::ZipArchive;
 
+use super::encryption::{BackupFile, MasterKey};
 use crate::try_option;
-use crate::utils::option_open;
 
 pub struct BackupReader {
-    backup: ZipArchive<File>,
+    backup: ZipArchive<BackupFile>,
+    master_key: Option<MasterKey>,
+}
+
+#[derive(Debug, Clone)]
+pub struct BackupEntry {
+    pub name: String,
+    pub size: u64,
+    pub is_dir: bool,
 }
 
 impl BackupReader {
     pub fn new(path: impl AsRef<Path>) -> Result<Option<BackupReader>> {
-        let file = try_option!(option_open(path));
+        let file = try_option!(BackupFile::open(path));
 
         Ok(Some(BackupReader {
+            master_key: file.master_key().cloned(),
             backup: ZipArchive::new(file)?,
         }))
     }
 
+    /// The key the backup is encrypted with, or None if it isn't encrypted
+    pub fn master_key(&self) -> Option<&MasterKey> {
+        self.master_key.as_ref()
+    }
+
     pub fn file_names(&self) -> impl Iterator<Item = &str> {
         self.backup.file_names()
     }
 
-    pub fn get_file(&mut self, name: &str) -> Option<ZipFile> {
-        match self.backup.by_name(name) {
-            Ok(v) => Some(v),
-            Err(e) => {
-                println!("{:?}", e);
-                None
+    pub fn get_file(&mut self, name: &str) -> Result<ZipFile<'_>> {
+        self.backup
+            .by_name(name)
+            .map_err(|e| anyhow!("Can't read `{}` from the backup: {}", name, e))
+    }
+
+    pub fn entries(&mut self) -> Result<Vec<BackupEntry>> {
+        let mut entries = Vec::with_capacity(self.backup.len());
+
+        for i in 0..self.backup.len() {
+            let file = self.backup.by_index(i)?;
+
+            entries.push(BackupEntry {
+                name: file.name().to_string(),
+                size: file.size(),
+                is_dir: file.is_dir(),
+            });
+        }
+
+        Ok(entries)
+    }
+
+    /// Reads every entry in the backup, returning the ones that can't be read or whose checksums don't match
+    pub fn corrupt_entries(&mut self) -> Vec<(String, Error)> {
+        let mut corrupt = Vec::new();
+
+        for i in 0..self.backup.len() {
+            let mut file = match self.backup.by_index(i) {
+                Ok(v) => v,
+                Err(e) => {
+                    corrupt.push((format!("entry #{}", i), e.into()));
+                    continue;
+                }
+            };
+
+            if let Err(e) = io::copy(&mut file, &mut io::sink()) {
+                corrupt.push((file.name().to_string(), e.into()));
             }
         }
+
+        corrupt
     }
 }
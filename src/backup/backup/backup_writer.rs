@@ -1,10 +1,11 @@
 use anyhow::Error;
 use anyhow::Result;
-use ring::digest::{digest, SHA256};
+use chrono::{DateTime, Utc};
+use std::collections::HashMap;
 use std::fs;
 use std::fs::File;
-use std::io::Read;
-use std::io::Write;
+use std::io;
+use std::io::{Read, Seek, SeekFrom, Write};
 use std::path::Path;
 use std::path::PathBuf;
 use zip::write::FileOptions;
@@ -13,30 +14,75 @@ use zip::ZipWriter;
 use crate::backup::BackupArgs;
 use crate::backup::BackupType;
 use crate::backup::BackupType::*;
-use crate::utils::option_open;
+use crate::backup::{CompressionPolicy, Storage};
+use crate::region::{
+    is_region_file, stream_region, RegionDeltaWriter, RegionReader, CHUNKS_PER_REGION,
+};
+use crate::utils::{option_open, IgnoreRules, TempFile};
 
+use super::encryption::{BackupFile, MasterKey};
+use super::manifest::{sha256_reader, HashingReader, HashingWriter, Manifest, ManifestEntry};
 use super::Backup;
+use super::BackupChain;
 use super::BackupData;
-use super::PREV_BACKUP_PREFIX;
+use super::ChunkStore;
+use super::{changed_chunks_entry, prev_backup_marker, CHANGED_CHUNKS_PREFIX, PREV_BACKUP_PREFIX};
 
 pub struct BackupWriter {
-    backup: ZipWriter<File>,
+    backup: ZipWriter<BackupFile>,
     source_dir: PathBuf,
     backup_type: BackupType,
+    paranoid: bool,
     data: BackupData,
+    manifest: Manifest,
+    previous_manifest: Option<HashMap<String, ManifestEntry>>,
+    /// Only loaded once a region file needs to be compared against its previous version
+    previous_chain: Option<BackupChain>,
+    /// Where the files go for chunked backups
+    store: Option<ChunkStore>,
+    compression: CompressionPolicy,
+    ignore: IgnoreRules,
+    /// The folder the backups are kept in with symlinks and `..` resolved, so that it's recognized however it's named
+    backups_dir: PathBuf,
 }
 
 impl BackupWriter {
+    /// `reference` is the backup to compare files against, the previous backup for zip backups or the most recent chunked backup for chunked ones.
+    /// The backup and its chunks are encrypted with `key` if there is one
     pub fn new(
         source_dir: &dyn AsRef<Path>,
         backups_data: BackupData,
         args: &BackupArgs,
+        reference: Option<Backup>,
+        key: Option<MasterKey>,
     ) -> Result<BackupWriter> {
+        let previous_manifest = match reference {
+            Some(v) => v.manifest()?.map(Manifest::into_map),
+            None => None,
+        };
+
+        let store = match args.storage {
+            Storage::Zip => None,
+            Storage::Chunks => Some(
+                ChunkStore::new(backups_data.current.parent().unwrap()).with_key(key.clone()),
+            ),
+        };
+
+        let backups_dir = fs::canonicalize(backups_data.current.parent().unwrap())?;
+
         Ok(BackupWriter {
             source_dir: source_dir.as_ref().to_path_buf(),
-            backup: ZipWriter::new(File::create(&backups_data.current)?),
+            backup: ZipWriter::new(BackupFile::create(&backups_data.current, key.as_ref())?),
             backup_type: args.backup_type,
+            paranoid: args.paranoid,
             data: backups_data,
+            manifest: Manifest::default(),
+            previous_manifest,
+            previous_chain: None,
+            store,
+            compression: args.compression.clone(),
+            ignore: IgnoreRules::new(&args.ignore)?,
+            backups_dir,
         })
     }
 
@@ -49,68 +95,244 @@ impl BackupWriter {
     }
 
     pub fn add_file(&mut self, source: &dyn AsRef<Path>) -> Result<()> {
-        let mut data = Vec::new();
-        option_open(source)?
-            .expect("Tried to add a file that doesn't exist to the backup")
-            .read_to_end(&mut data)?;
-
         let out_dir = self.out_dir(source)?;
+        let path = out_dir.to_str().unwrap().to_string();
+
+        let metadata = fs::metadata(source)?;
+        let modified = DateTime::<Utc>::from(metadata.modified()?).timestamp_millis();
 
-        match self.backup_type {
-            Partial => {
-                let _digest = digest(&SHA256, &data);
-                let hash = _digest.as_ref();
+        if self.store.is_some() {
+            return self.add_chunked_file(source, path, metadata.len(), modified);
+        }
+
+        if let (Partial, false, Some(previous)) = (
+            self.backup_type,
+            self.paranoid,
+            self.previous_manifest.as_ref().and_then(|v| v.get(&path)),
+        ) {
+            // Trust that the file hasn't changed if its size and modification time haven't, like rsync does
+            if previous.size == metadata.len() as i64 && previous.modified == modified {
+                let entry = previous.clone();
+
+                self.manifest.files.push(entry);
+                self.mark_in_prev(source)?;
 
+                return Ok(());
+            }
+        }
+
+        if let (Partial, Some(previous)) = (
+            self.backup_type,
+            self.previous_manifest.as_ref().and_then(|v| v.get(&path)),
+        ) {
+            if is_region_file(&path) && self.add_region(source, &previous.clone(), modified)? {
+                return Ok(());
+            }
+        }
+
+        let previous_hash = match (self.backup_type, &self.previous_manifest) {
+            (Full, _) => None,
+            (Partial, Some(manifest)) => manifest.get(&path).map(|v| v.hash.clone()),
+            // The previous backup was made by an older version without a manifest, so hash its copy of the file instead
+            (Partial, None) => {
                 match Backup::new(BackupData::clone(&self.data)).get_reader_with_file(&out_dir)? {
-                    Some(mut v) => {
-                        let mut prev_file = v.get_file(out_dir.to_str().unwrap()).unwrap();
-
-                        let mut prev_data = Vec::new();
-                        prev_file.read_to_end(&mut prev_data)?;
-
-                        let _digest = digest(&SHA256, &prev_data);
-                        let prev_hash = _digest.as_ref();
-
-                        if hash == prev_hash {
-                            self.mark_in_prev(source)?;
-                        } else {
-                            self.write_data(&mut data, source)?;
-                        }
-                    }
-                    None => {
-                        self.write_data(&mut data, source)?;
-                    }
+                    Some(mut v) => Some(sha256_reader(&mut v.get_file(&path)?)?.0),
+                    None => None,
                 }
             }
-            Full => {
-                self.write_data(&mut data, source)?;
+        };
+
+        if let Some(previous_hash) = previous_hash {
+            let (hash, size) = sha256_reader(&mut open_source(source)?)?;
+
+            if hash == previous_hash {
+                self.manifest.files.push(ManifestEntry {
+                    path,
+                    size: size as i64,
+                    modified,
+                    hash,
+                    chunks: Vec::new(),
+                });
+
+                return self.mark_in_prev(source);
             }
         }
 
+        let (hash, size) = self.write_data(&mut open_source(source)?, source)?;
+
+        self.manifest.files.push(ManifestEntry {
+            path,
+            size: size as i64,
+            modified,
+            hash,
+            chunks: Vec::new(),
+        });
+
         Ok(())
     }
 
-    fn write_data(&mut self, data: &mut [u8], source: &dyn AsRef<Path>) -> Result<()> {
+    fn add_chunked_file(
+        &mut self,
+        source: &dyn AsRef<Path>,
+        path: String,
+        size: u64,
+        modified: i64,
+    ) -> Result<()> {
+        let store = self.store.as_ref().unwrap();
+
+        if let (false, Some(previous)) = (
+            self.paranoid,
+            self.previous_manifest.as_ref().and_then(|v| v.get(&path)),
+        ) {
+            // Same as for partial backups, but the chunks also have to still be in the store
+            if previous.size == size as i64
+                && previous.modified == modified
+                && previous.chunks.iter().all(|v| store.contains(v))
+            {
+                self.manifest.files.push(previous.clone());
+
+                return Ok(());
+            }
+        }
+
+        let stored = store.store(&mut open_source(source)?)?;
+
+        self.manifest.files.push(ManifestEntry {
+            path,
+            size: stored.size as i64,
+            modified,
+            hash: stored.hash,
+            chunks: stored.chunks,
+        });
+
+        Ok(())
+    }
+
+    /// Stores only the chunks of a region file that changed since the previous backup, returning false if the region can't be compared chunk by chunk
+    fn add_region(
+        &mut self,
+        source: &dyn AsRef<Path>,
+        previous: &ManifestEntry,
+        modified: i64,
+    ) -> Result<bool> {
+        // Work from a copy so that the game saving the region in the meantime can't make the stored chunks disagree with the hash
+        let mut copy = TempFile::copy_from(&mut open_source(source)?)?;
+        let size = copy.seek(SeekFrom::End(0))?;
+
+        // Region files that can't be parsed are stored whole, chunks kept in separate .mcc files are backed up like any other file
+        let mut new = match RegionReader::new(copy) {
+            Ok(v) => v,
+            Err(_) => return Ok(false),
+        };
+
+        let mut old = match self.previous_chain()?.open_region(&previous.path, 0) {
+            Ok(Some(v)) => v,
+            _ => return Ok(false),
+        };
+
+        // Only one chunk of each version is in memory at a time
+        let mut present = vec![false; CHUNKS_PER_REGION];
+        let mut changed = vec![false; CHUNKS_PER_REGION];
+        let mut unchanged = true;
+
+        for index in 0..CHUNKS_PER_REGION {
+            let (new, old) = match (new.chunk(index), old.read_chunk(index)) {
+                (Ok(new), Ok(old)) => (new, old),
+                _ => return Ok(false),
+            };
+
+            present[index] = new.is_some();
+            changed[index] = new.is_some() && new != old;
+            unchanged &= new == old;
+        }
+
+        if unchanged {
+            self.manifest.files.push(ManifestEntry {
+                size: size as i64,
+                modified,
+                ..previous.clone()
+            });
+
+            self.mark_in_prev(source)?;
+
+            return Ok(true);
+        }
+
+        // Restoring rebuilds the region file rather than reproducing it byte for byte, so the hash is of the rebuilt file
+        let mut hasher = HashingWriter::new();
+        stream_region(&mut hasher, &mut |index| new.chunk(index))?;
+        let (hash, _) = hasher.finish();
+
+        let name = self.out_dir(&changed_chunks_entry(source))?;
+        self.backup
+            .start_file(name.to_str().unwrap(), self.compression.options_for(&name))?;
+
+        let mut delta = RegionDeltaWriter::new(&mut self.backup, &present)?;
+
+        for index in (0..CHUNKS_PER_REGION).filter(|v| changed[*v]) {
+            delta.write_chunk(index, &new.chunk(index)?.unwrap())?;
+        }
+
+        self.manifest.files.push(ManifestEntry {
+            path: previous.path.clone(),
+            size: size as i64,
+            modified,
+            hash,
+            chunks: Vec::new(),
+        });
+
+        Ok(true)
+    }
+
+    fn previous_chain(&mut self) -> Result<&BackupChain> {
+        if self.previous_chain.is_none() {
+            let previous = Backup::new(self.data.clone())
+                .prev()?
+                .ok_or(Error::msg("The previous backup disappeared"))?;
+
+            self.previous_chain = Some(BackupChain::new(previous)?);
+        }
+
+        Ok(self.previous_chain.as_ref().unwrap())
+    }
+
+    /// Every file that has been added to the backup, including files that are stored in previous backups
+    pub fn manifest(&self) -> &Manifest {
+        &self.manifest
+    }
+
+    /// Streams `data` into the backup, returning the hash and size of what was written
+    fn write_data(
+        &mut self,
+        data: &mut dyn Read,
+        source: &dyn AsRef<Path>,
+    ) -> Result<(String, u64)> {
         let dir = self.out_dir(source)?;
 
         self.backup
-            .start_file(dir.to_str().unwrap(), FileOptions::default())?;
-        self.backup.write_all(data)?;
+            .start_file(dir.to_str().unwrap(), self.compression.options_for(&dir))?;
 
-        Ok(())
+        let mut reader = HashingReader::new(data);
+        io::copy(&mut reader, &mut self.backup)?;
+
+        Ok(reader.finish())
     }
 
     fn mark_in_prev(&mut self, source: &dyn AsRef<Path>) -> Result<()> {
-        let dir = self.out_dir(source)?;
+        self.write_data(&mut io::empty(), &prev_backup_marker(source))?;
 
-        self.write_data(
-            &mut [],
-            &source
-                .as_ref()
-                .parent()
-                .unwrap()
-                .join(PREV_BACKUP_PREFIX.to_owned() + dir.file_name().unwrap().to_str().unwrap()),
-        )
+        Ok(())
+    }
+
+    /// Whether the file or folder is left out of the backup, either because it's ignored or because it's where the backups are kept
+    pub fn is_ignored(&self, source: &dyn AsRef<Path>) -> Result<bool> {
+        if source.as_ref().is_dir() && fs::canonicalize(source)? == self.backups_dir {
+            return Ok(true);
+        }
+
+        Ok(self
+            .ignore
+            .is_ignored(self.out_dir(source)?, source.as_ref().is_dir()))
     }
 
     pub fn add_directory(&mut self, source: &dyn AsRef<Path>) -> Result<()> {
@@ -123,15 +345,23 @@ impl BackupWriter {
     }
 
     pub fn add_new_file(&mut self, source: &dyn AsRef<Path>, data: &mut dyn Read) -> Result<()> {
-        let mut data_buf = Vec::new();
-        data.read_to_end(&mut data_buf)?;
+        self.write_data(data, source)?;
 
-        self.write_data(&mut data_buf, source)?;
+        Ok(())
+    }
+
+    /// Writes the end of the zip file, encrypted backups aren't readable until this is done
+    pub fn finish(mut self) -> Result<()> {
+        self.backup.finish()?.flush()?;
 
         Ok(())
     }
 }
 
+fn open_source(source: &dyn AsRef<Path>) -> Result<File> {
+    Ok(option_open(source)?.expect("Tried to add a file that doesn't exist to the backup"))
+}
+
 pub fn write_files_with_wd(writer: &mut BackupWriter, from_trait: &dyn AsRef<Path>) -> Result<()> {
     let from = from_trait.as_ref().to_path_buf();
 
@@ -146,14 +376,21 @@ pub fn write_files_with_wd(writer: &mut BackupWriter, from_trait: &dyn AsRef<Pat
                 continue;
             }
 
-            if &path_str.chars().take(7).collect::<String>() == PREV_BACKUP_PREFIX {
+            if let Some(prefix) = [PREV_BACKUP_PREFIX, CHANGED_CHUNKS_PREFIX]
+                .iter()
+                .find(|v| path_str.starts_with(*v))
+            {
                 return Err(Error::msg(format!(
                     "File names may not start with {} (this'd break incremental backups): {}",
-                    PREV_BACKUP_PREFIX,
+                    prefix,
                     from.join(path).to_str().unwrap()
                 )));
             }
 
+            if writer.is_ignored(&from.join(&path))? {
+                continue;
+            }
+
             write_files_with_wd(writer, &from.join(path))?;
         }
     } else if from.is_file() {
@@ -0,0 +1,288 @@
+use anyhow::{anyhow, Result};
+use std::collections::HashSet;
+use std::fs;
+use std::io;
+use std::io::{Cursor, Read};
+use std::path::{Path, PathBuf};
+use std::slice::Iter;
+
+use super::content_chunker::ContentChunker;
+use super::encryption::{decrypt_bytes, encrypt_bytes, is_encrypted, MasterKey};
+use super::manifest::{sha256_reader, HashingReader};
+use crate::utils::option_open;
+
+/// The folder in `.backups` that chunked backups keep their data in
+pub const CHUNK_STORE_DIR: &str = "chunks";
+
+/// A file split into chunks and stored in a `ChunkStore`
+pub struct StoredFile {
+    pub chunks: Vec<String>,
+    /// Hex encoded SHA-256 of the whole file
+    pub hash: String,
+    pub size: u64,
+}
+
+/// What `ChunkStore::sweep` removed or would remove
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SweepReport {
+    pub kept: usize,
+    pub removed: usize,
+    pub reclaimed: u64,
+}
+
+/// Content addressed storage shared by every chunked backup, each unique chunk of data is stored once in a file named after its hash
+pub struct ChunkStore {
+    dir: PathBuf,
+    /// New chunks are encrypted with this key, and named after a keyed hash instead of their SHA-256
+    key: Option<MasterKey>,
+}
+
+impl ChunkStore {
+    pub fn new(backups_dir: impl AsRef<Path>) -> ChunkStore {
+        ChunkStore {
+            dir: backups_dir.as_ref().join(CHUNK_STORE_DIR),
+            key: None,
+        }
+    }
+
+    pub fn with_key(self, key: Option<MasterKey>) -> ChunkStore {
+        ChunkStore { key, ..self }
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    fn chunk_path(&self, hash: &str) -> PathBuf {
+        self.dir.join(&hash[..2]).join(hash)
+    }
+
+    pub fn contains(&self, hash: &str) -> bool {
+        self.chunk_path(hash).is_file()
+    }
+
+    /// Splits `data` into chunks and stores the ones that aren't in the store yet
+    pub fn store(&self, data: &mut dyn Read) -> Result<StoredFile> {
+        let mut reader = HashingReader::new(data);
+        let mut chunker = ContentChunker::new(&mut reader);
+        let mut chunks = Vec::new();
+
+        while let Some(chunk) = chunker.next_chunk()? {
+            let hash = match &self.key {
+                Some(key) => key.chunk_id(&chunk),
+                None => sha256_reader(&mut &chunk[..])?.0,
+            };
+            let path = self.chunk_path(&hash);
+
+            if !path.is_file() {
+                fs::create_dir_all(path.parent().unwrap())?;
+
+                // Write somewhere else first so an interrupted backup can't leave a truncated chunk behind
+                let temp = path.with_extension(format!("tmp-{:08x}", rand::random::<u32>()));
+                match &self.key {
+                    Some(key) => fs::write(&temp, encrypt_bytes(&chunk, key)?)?,
+                    None => fs::write(&temp, &chunk)?,
+                }
+
+                fs::rename(&temp, &path)?;
+            }
+
+            chunks.push(hash);
+        }
+
+        drop(chunker);
+        let (hash, size) = reader.finish();
+
+        Ok(StoredFile { chunks, hash, size })
+    }
+
+    /// Reads a chunk, making sure it hasn't been corrupted
+    pub fn read_chunk(&self, hash: &str) -> Result<Vec<u8>> {
+        let mut data = Vec::new();
+
+        option_open(self.chunk_path(hash))?
+            .ok_or(anyhow!(
+                "The chunk `{}` is missing from the chunk store",
+                hash
+            ))?
+            .read_to_end(&mut data)?;
+
+        let expected = if is_encrypted(&data) {
+            let (plain, key) = decrypt_bytes(&data)
+                .map_err(|e| e.context(format!("Can't decrypt the chunk `{}`", hash)))?;
+            data = plain;
+
+            key.chunk_id(&data)
+        } else {
+            sha256_reader(&mut &data[..])?.0
+        };
+
+        if expected != hash {
+            return Err(anyhow!("The chunk `{}` is corrupt", hash));
+        }
+
+        Ok(data)
+    }
+
+    /// Removes every chunk that isn't in `referenced`, along with chunks left half written by interrupted backups
+    pub fn sweep(&self, referenced: &HashSet<&str>, dry_run: bool) -> Result<SweepReport> {
+        let mut report = SweepReport::default();
+
+        if !self.dir.is_dir() {
+            return Ok(report);
+        }
+
+        for prefix in fs::read_dir(&self.dir)? {
+            let prefix = prefix?.path();
+
+            if !prefix.is_dir() {
+                continue;
+            }
+
+            for chunk in fs::read_dir(&prefix)? {
+                let chunk = chunk?;
+                let name = chunk.file_name().to_string_lossy().to_string();
+
+                if referenced.contains(&name[..]) {
+                    report.kept += 1;
+                    continue;
+                }
+
+                report.removed += 1;
+                report.reclaimed += chunk.metadata()?.len();
+
+                if !dry_run {
+                    fs::remove_file(chunk.path())?;
+                }
+            }
+
+            if !dry_run && fs::read_dir(&prefix)?.next().is_none() {
+                fs::remove_dir(&prefix)?;
+            }
+        }
+
+        Ok(report)
+    }
+
+    /// Reads the file made up of `chunks` one chunk at a time
+    pub fn open<'a>(&'a self, chunks: &'a [String]) -> ChunkReader<'a> {
+        ChunkReader {
+            store: self,
+            chunks: chunks.iter(),
+            current: Cursor::new(Vec::new()),
+        }
+    }
+}
+
+pub struct ChunkReader<'a> {
+    store: &'a ChunkStore,
+    chunks: Iter<'a, String>,
+    current: Cursor<Vec<u8>>,
+}
+
+impl Read for ChunkReader<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            let amount = self.current.read(buf)?;
+
+            if amount != 0 || buf.is_empty() {
+                return Ok(amount);
+            }
+
+            let hash = match self.chunks.next() {
+                Some(v) => v,
+                None => return Ok(0),
+            };
+
+            let chunk = self
+                .store
+                .read_chunk(hash)
+                .map_err(|e| io::Error::other(e.to_string()))?;
+
+            self.current = Cursor::new(chunk);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::content_chunker::tests::data;
+    use super::*;
+    use crate::utils::TempDir;
+
+    fn stored_chunks(store: &ChunkStore) -> usize {
+        store.sweep(&HashSet::new(), true).unwrap().removed
+    }
+
+    fn read(store: &ChunkStore, file: &StoredFile) -> Vec<u8> {
+        let mut data = Vec::new();
+        store.open(&file.chunks).read_to_end(&mut data).unwrap();
+
+        data
+    }
+
+    #[test]
+    fn round_trip() {
+        let dir = TempDir::new();
+        let store = ChunkStore::new(dir.path());
+        let data = data(600 * 1024, 4);
+
+        let file = store.store(&mut &data[..]).unwrap();
+
+        assert_eq!(file.size, data.len() as u64);
+        assert_eq!(file.hash, sha256_reader(&mut &data[..]).unwrap().0);
+        assert_eq!(read(&store, &file), data);
+    }
+
+    #[test]
+    fn unchanged_chunks_are_stored_once() {
+        let dir = TempDir::new();
+        let store = ChunkStore::new(dir.path());
+
+        let first = data(2 * 1024 * 1024, 5);
+        let mut second = first.clone();
+        second.splice(1000..1000, vec![0xab; 100]);
+
+        let first = store.store(&mut &first[..]).unwrap();
+        let count = stored_chunks(&store);
+        let second = store.store(&mut &second[..]).unwrap();
+
+        let new = second
+            .chunks
+            .iter()
+            .filter(|v| !first.chunks.contains(v))
+            .count();
+
+        assert!(new <= 2);
+        assert_eq!(stored_chunks(&store), count + new);
+    }
+
+    #[test]
+    fn sweep_only_removes_unreferenced_chunks() {
+        let dir = TempDir::new();
+        let store = ChunkStore::new(dir.path());
+
+        let kept_data = data(300 * 1024, 6);
+        let kept = store.store(&mut &kept_data[..]).unwrap();
+        let removed = store.store(&mut &data(300 * 1024, 7)[..]).unwrap();
+
+        // Left behind by an interrupted backup
+        let temp = store
+            .chunk_path(&kept.chunks[0])
+            .with_extension("tmp-00000000");
+        fs::write(&temp, b"partial").unwrap();
+
+        let referenced = kept.chunks.iter().map(|v| &v[..]).collect::<HashSet<_>>();
+
+        let dry_run = store.sweep(&referenced, true).unwrap();
+        assert_eq!(dry_run.removed, removed.chunks.len() + 1);
+        assert!(removed.chunks.iter().all(|v| store.contains(v)));
+
+        let report = store.sweep(&referenced, false).unwrap();
+        assert_eq!(report.kept, kept.chunks.len());
+        assert_eq!(report.removed, dry_run.removed);
+        assert!(!removed.chunks.iter().any(|v| store.contains(v)));
+        assert!(!temp.exists());
+        assert_eq!(read(&store, &kept), kept_data);
+    }
+}
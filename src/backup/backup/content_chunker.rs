@@ -0,0 +1,174 @@
+use std::io;
+use std::io::Read;
+
+/// Chunks are never cut before this many bytes, unless the file ends
+const MIN_CHUNK_SIZE: usize = 16 * 1024;
+/// Chunks are always cut after this many bytes
+const MAX_CHUNK_SIZE: usize = 256 * 1024;
+/// A chunk is cut wherever the rolling hash has these bits unset, which averages out to a cut every 64 KiB
+const CUT_MASK: u64 = (1 << 16) - 1;
+
+/// Random values for each byte, used by the rolling hash
+const GEAR: [u64; 256] = gear_table();
+
+const fn gear_table() -> [u64; 256] {
+    let mut table = [0; 256];
+    let mut state: u64 = 0x6d63_6261_636b_7570;
+    let mut i = 0;
+
+    // splitmix64, so the table is the same on every machine and every version
+    while i < 256 {
+        state = state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+
+        let mut z = state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+
+        table[i] = z ^ (z >> 31);
+        i += 1;
+    }
+
+    table
+}
+
+/// Splits a stream into content-defined chunks, so inserting or removing bytes only changes the chunks around the edit
+pub struct ContentChunker<R> {
+    inner: R,
+    buf: Vec<u8>,
+    done: bool,
+}
+
+impl<R: Read> ContentChunker<R> {
+    pub fn new(inner: R) -> ContentChunker<R> {
+        ContentChunker {
+            inner,
+            buf: Vec::with_capacity(MAX_CHUNK_SIZE),
+            done: false,
+        }
+    }
+
+    pub fn next_chunk(&mut self) -> io::Result<Option<Vec<u8>>> {
+        while !self.done && self.buf.len() < MAX_CHUNK_SIZE {
+            let start = self.buf.len();
+            self.buf.resize(MAX_CHUNK_SIZE, 0);
+
+            match self.inner.read(&mut self.buf[start..]) {
+                Ok(0) => {
+                    self.buf.truncate(start);
+                    self.done = true;
+                }
+                Ok(amount) => self.buf.truncate(start + amount),
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => self.buf.truncate(start),
+                Err(e) => {
+                    self.buf.truncate(start);
+                    return Err(e);
+                }
+            }
+        }
+
+        if self.buf.is_empty() {
+            return Ok(None);
+        }
+
+        let cut = find_cut(&self.buf);
+        let rest = self.buf.split_off(cut);
+
+        Ok(Some(std::mem::replace(&mut self.buf, rest)))
+    }
+}
+
+fn find_cut(data: &[u8]) -> usize {
+    if data.len() <= MIN_CHUNK_SIZE {
+        return data.len();
+    }
+
+    let mut hash: u64 = 0;
+
+    for (i, byte) in data
+        .iter()
+        .enumerate()
+        .take(MAX_CHUNK_SIZE)
+        .skip(MIN_CHUNK_SIZE)
+    {
+        hash = (hash << 1).wrapping_add(GEAR[*byte as usize]);
+
+        if hash & CUT_MASK == 0 {
+            return i + 1;
+        }
+    }
+
+    data.len().min(MAX_CHUNK_SIZE)
+}
+
+#[cfg(test)]
+pub(super) mod tests {
+    use super::*;
+
+    /// Bytes that look random but are the same on every run
+    pub fn data(len: usize, seed: u64) -> Vec<u8> {
+        let mut state = seed;
+
+        (0..len)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 7;
+                state ^= state << 17;
+                state as u8
+            })
+            .collect()
+    }
+
+    fn chunks(data: &[u8]) -> Vec<Vec<u8>> {
+        let mut chunker = ContentChunker::new(data);
+        let mut chunks = Vec::new();
+
+        while let Some(chunk) = chunker.next_chunk().unwrap() {
+            chunks.push(chunk);
+        }
+
+        chunks
+    }
+
+    #[test]
+    fn chunks_are_bounded_and_cover_the_data() {
+        let data = data(2 * 1024 * 1024, 1);
+        let chunks = chunks(&data);
+
+        assert!(chunks.len() > 1);
+        assert!(chunks.iter().all(|v| v.len() <= MAX_CHUNK_SIZE));
+        assert!(chunks[..chunks.len() - 1]
+            .iter()
+            .all(|v| v.len() > MIN_CHUNK_SIZE));
+        assert_eq!(chunks.concat(), data);
+    }
+
+    #[test]
+    fn the_same_data_gives_the_same_chunks() {
+        let data = data(1024 * 1024, 2);
+
+        assert_eq!(chunks(&data), chunks(&data));
+    }
+
+    #[test]
+    fn inserting_bytes_only_changes_the_chunks_around_them() {
+        let original = data(2 * 1024 * 1024, 3);
+        let mut edited = original.clone();
+        edited.splice(1000..1000, vec![0xab; 100]);
+
+        let before = chunks(&original);
+        let after = chunks(&edited);
+        let changed = after.iter().filter(|v| !before.contains(v)).count();
+
+        assert!(
+            changed <= 2,
+            "{} of {} chunks changed",
+            changed,
+            after.len()
+        );
+    }
+
+    #[test]
+    fn empty_data_has_no_chunks() {
+        assert!(chunks(&[]).is_empty());
+    }
+}
@@ -0,0 +1,829 @@
+use anyhow::{anyhow, Error, Result};
+use clap::ArgMatches;
+use ring::aead::{Aad, LessSafeKey, Nonce, UnboundKey, AES_256_GCM, NONCE_LEN};
+use ring::rand::{SecureRandom, SystemRandom};
+use ring::{hkdf, hmac, pbkdf2};
+use std::convert::TryInto;
+use std::env;
+use std::fs;
+use std::fs::{File, OpenOptions};
+use std::io;
+use std::io::{Cursor, Read, Seek, SeekFrom, Write};
+use std::num::NonZeroU32;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Mutex, OnceLock};
+
+use crate::try_option;
+use crate::utils::option_open;
+
+/// The first bytes of every encrypted file, plain zip files start with `PK`
+pub const ENCRYPTION_MAGIC: &[u8; 8] = b"MCBKENC1";
+/// The environment variable the passphrase can be given in instead of `--passphrase-file`
+pub const PASSPHRASE_ENV: &str = "MCBACKUP_PASSPHRASE";
+/// The file in `.backups` holding the salt passphrases are stretched with
+const SALT_FILE: &str = ".encryption-salt";
+
+/// Files are encrypted in segments of this many bytes, so they can be read and written at any position
+const SEGMENT_SIZE: usize = 64 * 1024;
+const TAG_LEN: usize = 16;
+const SALT_LEN: usize = 16;
+const KEY_LEN: usize = 32;
+const PBKDF2_ITERATIONS: u32 = 100_000;
+
+/// Magic, key derivation, its salt and iterations, the file's salt and length, then a nonce and tag authenticating all of those
+const HEADER_LEN: usize = 8 + 1 + SALT_LEN + 4 + SALT_LEN + 8 + NONCE_LEN + TAG_LEN;
+const AUTHENTICATED_HEADER_LEN: usize = HEADER_LEN - NONCE_LEN - TAG_LEN;
+const SEGMENT_LEN: usize = NONCE_LEN + SEGMENT_SIZE + TAG_LEN;
+
+static KEY_SOURCE: OnceLock<KeySource> = OnceLock::new();
+static ALLOW_UNENCRYPTED: AtomicBool = AtomicBool::new(false);
+/// Stretching a passphrase is slow on purpose, so it's only done once for each salt
+static MASTER_KEYS: Mutex<Vec<MasterKey>> = Mutex::new(Vec::new());
+
+/// What the user gave to encrypt and decrypt backups with
+pub enum KeySource {
+    KeyFile(Vec<u8>),
+    Passphrase(String),
+}
+
+impl KeySource {
+    /// Reads the key given with `--key-file`, `--passphrase-file` or `MCBACKUP_PASSPHRASE`
+    pub fn from_args(args: &ArgMatches) -> Result<Option<KeySource>> {
+        match (args.value_of("key-file"), args.value_of("passphrase-file")) {
+            (Some(_), Some(_)) => Err(Error::msg(
+                "Give either a key file or a passphrase, not both",
+            )),
+            (Some(path), None) => {
+                let key = fs::read(path)
+                    .map_err(|e| anyhow!("Can't read the key file `{}`: {}", path, e))?;
+
+                if key.len() < KEY_LEN {
+                    return Err(anyhow!(
+                        "The key file `{}` must be at least {} bytes long, make one with `head -c {} /dev/urandom > {}`",
+                        path,
+                        KEY_LEN,
+                        KEY_LEN,
+                        path
+                    ));
+                }
+
+                Ok(Some(KeySource::KeyFile(key)))
+            }
+            (None, Some(path)) => {
+                let passphrase = fs::read_to_string(path)
+                    .map_err(|e| anyhow!("Can't read the passphrase file `{}`: {}", path, e))?;
+
+                KeySource::passphrase(passphrase.trim_end_matches(['\r', '\n']))
+            }
+            (None, None) => match env::var(PASSPHRASE_ENV) {
+                Ok(v) => KeySource::passphrase(&v),
+                Err(_) => Ok(None),
+            },
+        }
+    }
+
+    fn passphrase(passphrase: &str) -> Result<Option<KeySource>> {
+        if passphrase.is_empty() {
+            return Err(Error::msg("The passphrase is empty"));
+        }
+
+        Ok(Some(KeySource::Passphrase(passphrase.to_string())))
+    }
+
+    /// Makes the key available to everything that reads or writes backups
+    pub fn install(self) {
+        let _ = KEY_SOURCE.set(self);
+    }
+
+    /// Whether a key was given, without one encrypted backups can't be read
+    pub fn is_installed() -> bool {
+        KEY_SOURCE.get().is_some()
+    }
+
+    /// Lets backups that aren't encrypted be read even though a key was given, which `--allow-unencrypted` opts into
+    pub fn allow_unencrypted(allow: bool) {
+        ALLOW_UNENCRYPTED.store(allow, Ordering::Relaxed);
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Kdf {
+    KeyFile,
+    Passphrase,
+}
+
+/// The key every other key of an encrypted file is derived from, along with how it was derived
+#[derive(Clone)]
+pub struct MasterKey {
+    kdf: Kdf,
+    salt: [u8; SALT_LEN],
+    iterations: u32,
+    key: [u8; KEY_LEN],
+}
+
+impl MasterKey {
+    /// The key new files in the repository get encrypted with, or None if no key was given
+    pub fn for_repository(backups_dir: impl AsRef<Path>) -> Result<Option<MasterKey>> {
+        match try_option!(no_try, KEY_SOURCE.get()) {
+            KeySource::KeyFile(_) => Ok(Some(MasterKey::derive(Kdf::KeyFile, [0; SALT_LEN], 0)?)),
+            KeySource::Passphrase(_) => {
+                let salt_path = backups_dir.as_ref().join(SALT_FILE);
+
+                let salt = match fs::read(&salt_path) {
+                    Ok(v) if v.len() == SALT_LEN => v.try_into().unwrap(),
+                    Ok(_) => return Err(anyhow!("`{}` is corrupt", salt_path.display())),
+                    Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                        let salt = random()?;
+                        fs::write(&salt_path, salt)?;
+                        salt
+                    }
+                    Err(e) => return Err(e.into()),
+                };
+
+                Ok(Some(MasterKey::derive(
+                    Kdf::Passphrase,
+                    salt,
+                    PBKDF2_ITERATIONS,
+                )?))
+            }
+        }
+    }
+
+    fn derive(kdf: Kdf, salt: [u8; SALT_LEN], iterations: u32) -> Result<MasterKey> {
+        let mut cache = MASTER_KEYS.lock().unwrap();
+
+        if let Some(key) = cache
+            .iter()
+            .find(|v| v.kdf == kdf && v.salt == salt && v.iterations == iterations)
+        {
+            return Ok(key.clone());
+        }
+
+        let source = KEY_SOURCE.get().ok_or(anyhow!(
+            "The backup is encrypted, give the key with --key-file, --passphrase-file or {}",
+            PASSPHRASE_ENV
+        ))?;
+
+        let mut key = [0; KEY_LEN];
+
+        match (kdf, source) {
+            (Kdf::KeyFile, KeySource::KeyFile(bytes)) => {
+                key = hkdf(b"minecraft-backup-manager key file", bytes, b"master key")
+            }
+            (Kdf::Passphrase, KeySource::Passphrase(passphrase)) => pbkdf2::derive(
+                pbkdf2::PBKDF2_HMAC_SHA256,
+                NonZeroU32::new(iterations)
+                    .ok_or(Error::msg("The encryption header is corrupt"))?,
+                &salt,
+                passphrase.as_bytes(),
+                &mut key,
+            ),
+            (Kdf::KeyFile, _) => {
+                return Err(Error::msg(
+                    "The backup is encrypted with a key file, but a passphrase was given",
+                ))
+            }
+            (Kdf::Passphrase, _) => {
+                return Err(Error::msg(
+                    "The backup is encrypted with a passphrase, but a key file was given",
+                ))
+            }
+        }
+
+        let key = MasterKey {
+            kdf,
+            salt,
+            iterations,
+            key,
+        };
+
+        cache.push(key.clone());
+
+        Ok(key)
+    }
+
+    /// `passphrase` or `key file`
+    pub fn kind(&self) -> &'static str {
+        match self.kdf {
+            Kdf::KeyFile => "key file",
+            Kdf::Passphrase => "passphrase",
+        }
+    }
+
+    fn file_key(&self, file_salt: &[u8]) -> LessSafeKey {
+        let key = hkdf(file_salt, &self.key, b"file key");
+
+        LessSafeKey::new(UnboundKey::new(&AES_256_GCM, &key).unwrap())
+    }
+
+    /// The name of an encrypted chunk in the chunk store, a keyed hash so the names don't reveal what the chunks contain
+    pub fn chunk_id(&self, data: &[u8]) -> String {
+        let key = hmac::Key::new(hmac::HMAC_SHA256, &hkdf(&[], &self.key, b"chunk ids"));
+
+        hmac::sign(&key, data)
+            .as_ref()
+            .iter()
+            .map(|v| format!("{:02x}", v))
+            .collect()
+    }
+}
+
+struct KeyLen;
+
+impl hkdf::KeyType for KeyLen {
+    fn len(&self) -> usize {
+        KEY_LEN
+    }
+}
+
+fn hkdf(salt: &[u8], secret: &[u8], info: &[u8]) -> [u8; KEY_LEN] {
+    let mut key = [0; KEY_LEN];
+
+    hkdf::Salt::new(hkdf::HKDF_SHA256, salt)
+        .extract(secret)
+        .expand(&[info], KeyLen)
+        .and_then(|v| v.fill(&mut key))
+        .expect("32 bytes is a valid HKDF-SHA256 output length");
+
+    key
+}
+
+fn random<const N: usize>() -> io::Result<[u8; N]> {
+    let mut bytes = [0; N];
+
+    SystemRandom::new()
+        .fill(&mut bytes)
+        .map_err(|_| io::Error::other("Couldn't generate random bytes"))?;
+
+    Ok(bytes)
+}
+
+fn corrupt() -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        "The encrypted file is corrupt or has been tampered with",
+    )
+}
+
+/// A file encrypted with AES-256-GCM that can be read, written and seeked like the plain file it holds
+///
+/// The file is split into segments that are each encrypted with a random nonce, so rewriting part of the file never reuses one.
+/// Changes are only complete once the file is flushed
+pub struct EncryptedFile<F> {
+    inner: F,
+    master: MasterKey,
+    file_salt: [u8; SALT_LEN],
+    key: LessSafeKey,
+    len: u64,
+    pos: u64,
+    segment: Vec<u8>,
+    segment_index: Option<u64>,
+    dirty: bool,
+    header_dirty: bool,
+}
+
+impl<F: Read + Write + Seek> EncryptedFile<F> {
+    pub fn create(inner: F, master: &MasterKey) -> Result<EncryptedFile<F>> {
+        let file_salt = random()?;
+
+        let mut file = EncryptedFile {
+            inner,
+            master: master.clone(),
+            file_salt,
+            key: master.file_key(&file_salt),
+            len: 0,
+            pos: 0,
+            segment: Vec::new(),
+            segment_index: None,
+            dirty: false,
+            header_dirty: false,
+        };
+
+        file.write_header()?;
+
+        Ok(file)
+    }
+
+    pub fn open(mut inner: F) -> Result<EncryptedFile<F>> {
+        let mut header = [0; HEADER_LEN];
+        inner.seek(SeekFrom::Start(0))?;
+        inner.read_exact(&mut header)?;
+
+        if &header[..8] != ENCRYPTION_MAGIC {
+            return Err(Error::msg("The file isn't encrypted"));
+        }
+
+        let kdf = match header[8] {
+            0 => Kdf::KeyFile,
+            1 => Kdf::Passphrase,
+            _ => {
+                return Err(Error::msg(
+                    "The file is encrypted in a way this version doesn't know",
+                ))
+            }
+        };
+
+        let master = MasterKey::derive(
+            kdf,
+            header[9..25].try_into().unwrap(),
+            u32::from_be_bytes(header[25..29].try_into().unwrap()),
+        )?;
+
+        let file_salt: [u8; SALT_LEN] = header[29..45].try_into().unwrap();
+        let key = master.file_key(&file_salt);
+
+        let (authenticated, rest) = header.split_at_mut(AUTHENTICATED_HEADER_LEN);
+        let (nonce, tag) = rest.split_at_mut(NONCE_LEN);
+
+        key.open_in_place(
+            Nonce::try_assume_unique_for_key(nonce).unwrap(),
+            Aad::from(&authenticated[..]),
+            tag,
+        )
+        .map_err(|_| Error::msg("The key is wrong, or the encrypted file is corrupt"))?;
+
+        Ok(EncryptedFile {
+            inner,
+            master,
+            file_salt,
+            key,
+            len: u64::from_be_bytes(authenticated[45..53].try_into().unwrap()),
+            pos: 0,
+            segment: Vec::new(),
+            segment_index: None,
+            dirty: false,
+            header_dirty: false,
+        })
+    }
+
+    pub fn master_key(&self) -> &MasterKey {
+        &self.master
+    }
+
+    pub fn into_inner(self) -> F {
+        self.inner
+    }
+
+    fn write_header(&mut self) -> io::Result<()> {
+        let mut header = Vec::with_capacity(HEADER_LEN);
+        header.extend_from_slice(ENCRYPTION_MAGIC);
+        header.push(match self.master.kdf {
+            Kdf::KeyFile => 0,
+            Kdf::Passphrase => 1,
+        });
+        header.extend_from_slice(&self.master.salt);
+        header.extend_from_slice(&self.master.iterations.to_be_bytes());
+        header.extend_from_slice(&self.file_salt);
+        header.extend_from_slice(&self.len.to_be_bytes());
+
+        let nonce = random()?;
+        let tag = self
+            .key
+            .seal_in_place_separate_tag(
+                Nonce::assume_unique_for_key(nonce),
+                Aad::from(&header[..]),
+                &mut [],
+            )
+            .map_err(|_| io::Error::other("Couldn't encrypt the file"))?;
+
+        header.extend_from_slice(&nonce);
+        header.extend_from_slice(tag.as_ref());
+
+        self.inner.seek(SeekFrom::Start(0))?;
+        self.inner.write_all(&header)
+    }
+
+    /// Ties each segment to its position in this file, so segments can't be swapped around or between files
+    fn segment_aad(&self, index: u64) -> [u8; SALT_LEN + 8] {
+        let mut aad = [0; SALT_LEN + 8];
+        aad[..SALT_LEN].copy_from_slice(&self.file_salt);
+        aad[SALT_LEN..].copy_from_slice(&index.to_be_bytes());
+        aad
+    }
+
+    fn load_segment(&mut self, index: u64) -> io::Result<()> {
+        if self.segment_index == Some(index) {
+            return Ok(());
+        }
+
+        self.flush_segment()?;
+        self.segment.clear();
+        self.segment_index = None;
+
+        let start = index * SEGMENT_SIZE as u64;
+
+        if start < self.len {
+            let plain_len = (self.len - start).min(SEGMENT_SIZE as u64) as usize;
+            let mut buf = vec![0; NONCE_LEN + plain_len + TAG_LEN];
+
+            self.inner.seek(SeekFrom::Start(
+                HEADER_LEN as u64 + index * SEGMENT_LEN as u64,
+            ))?;
+            self.inner.read_exact(&mut buf)?;
+
+            let aad = self.segment_aad(index);
+            let (nonce, sealed) = buf.split_at_mut(NONCE_LEN);
+
+            let plain = self
+                .key
+                .open_in_place(
+                    Nonce::try_assume_unique_for_key(nonce).unwrap(),
+                    Aad::from(&aad[..]),
+                    sealed,
+                )
+                .map_err(|_| corrupt())?;
+
+            self.segment.extend_from_slice(plain);
+        }
+
+        self.segment_index = Some(index);
+
+        Ok(())
+    }
+
+    fn flush_segment(&mut self) -> io::Result<()> {
+        let index = match (self.dirty, self.segment_index) {
+            (true, Some(v)) => v,
+            _ => return Ok(()),
+        };
+
+        let aad = self.segment_aad(index);
+        let nonce = random()?;
+        let mut sealed = self.segment.clone();
+
+        self.key
+            .seal_in_place_append_tag(
+                Nonce::assume_unique_for_key(nonce),
+                Aad::from(&aad[..]),
+                &mut sealed,
+            )
+            .map_err(|_| io::Error::other("Couldn't encrypt the file"))?;
+
+        self.inner.seek(SeekFrom::Start(
+            HEADER_LEN as u64 + index * SEGMENT_LEN as u64,
+        ))?;
+        self.inner.write_all(&nonce)?;
+        self.inner.write_all(&sealed)?;
+
+        self.dirty = false;
+
+        Ok(())
+    }
+}
+
+impl<F: Read + Write + Seek> Read for EncryptedFile<F> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if buf.is_empty() || self.pos >= self.len {
+            return Ok(0);
+        }
+
+        self.load_segment(self.pos / SEGMENT_SIZE as u64)?;
+
+        let offset = (self.pos % SEGMENT_SIZE as u64) as usize;
+        let amount = (self.segment.len() - offset).min(buf.len());
+
+        buf[..amount].copy_from_slice(&self.segment[offset..offset + amount]);
+        self.pos += amount as u64;
+
+        Ok(amount)
+    }
+}
+
+impl<F: Read + Write + Seek> Write for EncryptedFile<F> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+
+        // Segments can't have holes, so a gap left by seeking past the end is filled with zeros
+        if self.pos > self.len {
+            let pos = self.pos;
+            self.pos = self.len;
+
+            while self.pos < pos {
+                let gap = (pos - self.pos).min(SEGMENT_SIZE as u64) as usize;
+                self.write_all(&vec![0; gap])?;
+            }
+        }
+
+        self.load_segment(self.pos / SEGMENT_SIZE as u64)?;
+
+        let offset = (self.pos % SEGMENT_SIZE as u64) as usize;
+        let amount = (SEGMENT_SIZE - offset).min(buf.len());
+
+        if self.segment.len() < offset + amount {
+            self.segment.resize(offset + amount, 0);
+        }
+
+        self.segment[offset..offset + amount].copy_from_slice(&buf[..amount]);
+        self.dirty = true;
+        self.pos += amount as u64;
+
+        if self.pos > self.len {
+            self.len = self.pos;
+            self.header_dirty = true;
+        }
+
+        Ok(amount)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.flush_segment()?;
+
+        if self.header_dirty {
+            self.write_header()?;
+            self.header_dirty = false;
+        }
+
+        self.inner.flush()
+    }
+}
+
+impl<F: Read + Write + Seek> Seek for EncryptedFile<F> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let pos = match pos {
+            SeekFrom::Start(v) => Some(v),
+            SeekFrom::End(v) => self.len.checked_add_signed(v),
+            SeekFrom::Current(v) => self.pos.checked_add_signed(v),
+        };
+
+        self.pos = pos.ok_or(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "Tried to seek before the start of the file",
+        ))?;
+
+        Ok(self.pos)
+    }
+}
+
+pub fn is_encrypted(bytes: &[u8]) -> bool {
+    bytes.starts_with(ENCRYPTION_MAGIC)
+}
+
+pub fn encrypt_bytes(data: &[u8], master: &MasterKey) -> Result<Vec<u8>> {
+    let mut file = EncryptedFile::create(Cursor::new(Vec::new()), master)?;
+    file.write_all(data)?;
+    file.flush()?;
+
+    Ok(file.into_inner().into_inner())
+}
+
+/// Decrypts a whole encrypted file, returning its contents and the key it was encrypted with
+pub fn decrypt_bytes(bytes: &[u8]) -> Result<(Vec<u8>, MasterKey)> {
+    let mut file = EncryptedFile::open(Cursor::new(bytes.to_vec()))?;
+    let mut data = Vec::new();
+    file.read_to_end(&mut data)?;
+
+    Ok((data, file.master))
+}
+
+fn has_encryption_header(file: &mut File) -> Result<bool> {
+    let mut magic = [0; 8];
+
+    match file.read_exact(&mut magic) {
+        Ok(()) => Ok(&magic == ENCRYPTION_MAGIC),
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => Ok(false),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// A backup's zip file, which is transparently decrypted if it's encrypted
+pub enum BackupFile {
+    Plain(File),
+    Encrypted(Box<EncryptedFile<File>>),
+}
+
+impl BackupFile {
+    pub fn open(path: impl AsRef<Path>) -> Result<Option<BackupFile>> {
+        BackupFile::open_checked(
+            path,
+            KeySource::is_installed() && !ALLOW_UNENCRYPTED.load(Ordering::Relaxed),
+        )
+    }
+
+    /// Opens the file, refusing it if it isn't encrypted and `require_encryption` is set
+    fn open_checked(
+        path: impl AsRef<Path>,
+        require_encryption: bool,
+    ) -> Result<Option<BackupFile>> {
+        let mut file = try_option!(option_open(&path));
+
+        let encrypted = has_encryption_header(&mut file)?;
+
+        file.seek(SeekFrom::Start(0))?;
+
+        // Nothing authenticates a plain file, so anyone who can write to the backups could have swapped an encrypted backup for it
+        if !encrypted && require_encryption {
+            return Err(anyhow!(
+                "`{}` isn't encrypted even though a key was given, so it could have been tampered with. Use --allow-unencrypted to read it anyway",
+                path.as_ref().file_name().unwrap().to_string_lossy()
+            ));
+        }
+
+        if !encrypted {
+            return Ok(Some(BackupFile::Plain(file)));
+        }
+
+        let file = EncryptedFile::open(file).map_err(|e| {
+            e.context(format!(
+                "Can't decrypt `{}`",
+                path.as_ref().file_name().unwrap().to_string_lossy()
+            ))
+        })?;
+
+        Ok(Some(BackupFile::Encrypted(Box::new(file))))
+    }
+
+    /// Whether the file at `path` is encrypted, which only needs its header and not the key
+    pub fn is_encrypted(path: impl AsRef<Path>) -> Result<bool> {
+        has_encryption_header(&mut File::open(path)?)
+    }
+
+    /// Creates a file that's encrypted with `key`, or a plain one if there's no key
+    pub fn create(path: impl AsRef<Path>, key: Option<&MasterKey>) -> Result<BackupFile> {
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(path)?;
+
+        Ok(match key {
+            Some(key) => BackupFile::Encrypted(Box::new(EncryptedFile::create(file, key)?)),
+            None => BackupFile::Plain(file),
+        })
+    }
+
+    pub fn master_key(&self) -> Option<&MasterKey> {
+        match self {
+            BackupFile::Plain(_) => None,
+            BackupFile::Encrypted(v) => Some(v.master_key()),
+        }
+    }
+}
+
+impl Read for BackupFile {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            BackupFile::Plain(v) => v.read(buf),
+            BackupFile::Encrypted(v) => v.read(buf),
+        }
+    }
+}
+
+impl Write for BackupFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            BackupFile::Plain(v) => v.write(buf),
+            BackupFile::Encrypted(v) => v.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            BackupFile::Plain(v) => v.flush(),
+            BackupFile::Encrypted(v) => v.flush(),
+        }
+    }
+}
+
+impl Seek for BackupFile {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        match self {
+            BackupFile::Plain(v) => v.seek(pos),
+            BackupFile::Encrypted(v) => v.seek(pos),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::TempDir;
+
+    /// A key made up of `key`, with a salt no other test uses so the cache of derived keys can't mix them up
+    fn test_key(salt: u8, key: u8) -> MasterKey {
+        MasterKey {
+            kdf: Kdf::Passphrase,
+            salt: [salt; SALT_LEN],
+            iterations: 1,
+            key: [key; KEY_LEN],
+        }
+    }
+
+    /// Makes `key` the one that files encrypted with its salt are opened with
+    fn install(key: &MasterKey) {
+        MASTER_KEYS.lock().unwrap().push(key.clone());
+    }
+
+    fn data(len: usize) -> Vec<u8> {
+        (0..len).map(|v| (v * 7 + v / 251) as u8).collect()
+    }
+
+    #[test]
+    fn round_trip() {
+        let key = test_key(1, 1);
+        install(&key);
+
+        for len in [0, 1, SEGMENT_SIZE, 2 * SEGMENT_SIZE + 123] {
+            let encrypted = encrypt_bytes(&data(len), &key).unwrap();
+
+            assert!(is_encrypted(&encrypted));
+            assert_eq!(decrypt_bytes(&encrypted).unwrap().0, data(len));
+        }
+    }
+
+    #[test]
+    fn seek_and_rewrite() {
+        let key = test_key(2, 2);
+        install(&key);
+
+        let mut expected = data(SEGMENT_SIZE + 1000);
+        let mut file = EncryptedFile::create(Cursor::new(Vec::new()), &key).unwrap();
+        file.write_all(&expected).unwrap();
+
+        // Like ZipWriter filling in a local header once the file after it is written
+        file.seek(SeekFrom::Start(10)).unwrap();
+        file.write_all(b"head").unwrap();
+        file.seek(SeekFrom::End(0)).unwrap();
+        file.write_all(b"tail").unwrap();
+        file.flush().unwrap();
+
+        expected[10..14].copy_from_slice(b"head");
+        expected.extend_from_slice(b"tail");
+
+        let encrypted = file.into_inner().into_inner();
+        assert_eq!(decrypt_bytes(&encrypted).unwrap().0, expected);
+    }
+
+    #[test]
+    fn rejects_truncated_files() {
+        let key = test_key(3, 3);
+        install(&key);
+
+        let mut encrypted = encrypt_bytes(&data(2 * SEGMENT_SIZE + 5), &key).unwrap();
+        encrypted.truncate(HEADER_LEN + 2 * SEGMENT_LEN);
+
+        assert!(decrypt_bytes(&encrypted).is_err());
+    }
+
+    #[test]
+    fn rejects_reordered_segments() {
+        let key = test_key(4, 4);
+        install(&key);
+
+        let mut encrypted = encrypt_bytes(&data(3 * SEGMENT_SIZE), &key).unwrap();
+        let (first, second) = encrypted[HEADER_LEN..].split_at_mut(SEGMENT_LEN);
+        first.swap_with_slice(&mut second[..SEGMENT_LEN]);
+
+        assert!(decrypt_bytes(&encrypted).is_err());
+    }
+
+    #[test]
+    fn rejects_the_wrong_key() {
+        let key = test_key(5, 5);
+        install(&test_key(5, 6));
+
+        let encrypted = encrypt_bytes(&data(100), &key).unwrap();
+
+        assert!(decrypt_bytes(&encrypted).is_err());
+    }
+
+    #[test]
+    fn rejects_plain_files_when_encryption_is_required() {
+        let key = test_key(7, 7);
+        install(&key);
+
+        let dir = TempDir::new();
+        let plain = dir.path().join("plain.zip");
+        let encrypted = dir.path().join("encrypted.zip");
+
+        fs::write(&plain, b"PK").unwrap();
+        fs::write(&encrypted, encrypt_bytes(b"PK", &key).unwrap()).unwrap();
+
+        assert!(BackupFile::open_checked(&plain, true).is_err());
+        assert!(matches!(
+            BackupFile::open_checked(&plain, false),
+            Ok(Some(BackupFile::Plain(_)))
+        ));
+        assert!(matches!(
+            BackupFile::open_checked(&encrypted, true),
+            Ok(Some(BackupFile::Encrypted(_)))
+        ));
+    }
+
+    #[test]
+    fn chunk_ids_are_stable() {
+        let key = test_key(6, 7);
+
+        assert_eq!(key.chunk_id(b"chunk"), key.chunk_id(b"chunk"));
+        assert_ne!(key.chunk_id(b"chunk"), key.chunk_id(b"other chunk"));
+        assert_ne!(key.chunk_id(b"chunk"), test_key(6, 8).chunk_id(b"chunk"));
+        // Changing how ids are derived would orphan every chunk already in a store
+        assert_eq!(
+            key.chunk_id(b"chunk"),
+            "efbf5315b71eb822becb0ddfd45404f39ef168dd02e7721a3f0b9b2fc861bda0"
+        );
+    }
+}
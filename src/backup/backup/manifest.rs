@@ -0,0 +1,151 @@
+use anyhow::Result;
+use quartz_nbt::io::Flavor;
+use quartz_nbt::serde::{deserialize, serialize};
+use ring::digest::{Context, SHA256};
+use serde::Deserialize;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::io::{self, Read, Write};
+use std::path::Path;
+
+use crate::utils::option_open;
+
+pub const MANIFEST_FILE: &str = "manifest.nbt";
+
+/// What a file looked like when it was backed up
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ManifestEntry {
+    pub path: String,
+    pub size: i64,
+    /// Unix timestamp in milliseconds of when the file was last modified
+    pub modified: i64,
+    /// Hex encoded SHA-256 of the file's contents
+    pub hash: String,
+    /// The hashes of the pieces the file is split into in the chunk store, only used by chunked backups
+    #[serde(default)]
+    pub chunks: Vec<String>,
+}
+
+/// Every file in a backup, including the ones stored in previous backups
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct Manifest {
+    pub files: Vec<ManifestEntry>,
+}
+
+impl Manifest {
+    pub fn to_bytes(&self) -> Result<Vec<u8>> {
+        Ok(serialize(self, Some(""), Flavor::Uncompressed)?)
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Manifest> {
+        Ok(deserialize::<Manifest>(bytes, Flavor::Uncompressed)?.0)
+    }
+
+    /// Compares the files in `dir` against the manifest, returning the paths of the ones that are missing or different
+    pub fn mismatches(&self, dir: &Path) -> Result<Vec<String>> {
+        let mut mismatches = Vec::new();
+
+        for entry in &self.files {
+            let matches = match option_open(dir.join(&entry.path))? {
+                Some(mut file) => sha256_reader(&mut file)?.0 == entry.hash,
+                None => false,
+            };
+
+            if !matches {
+                mismatches.push(entry.path.clone());
+            }
+        }
+
+        Ok(mismatches)
+    }
+
+    pub fn into_map(self) -> HashMap<String, ManifestEntry> {
+        self.files
+            .into_iter()
+            .map(|v| (v.path.clone(), v))
+            .collect()
+    }
+}
+
+/// Hashes everything that's read through it
+pub struct HashingReader<R> {
+    inner: R,
+    context: Context,
+    size: u64,
+}
+
+impl<R: Read> HashingReader<R> {
+    pub fn new(inner: R) -> HashingReader<R> {
+        HashingReader {
+            inner,
+            context: Context::new(&SHA256),
+            size: 0,
+        }
+    }
+
+    /// The hex encoded SHA-256 and the amount of bytes of everything that was read
+    pub fn finish(self) -> (String, u64) {
+        (to_hex(self.context), self.size)
+    }
+}
+
+impl<R: Read> Read for HashingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let amount = self.inner.read(buf)?;
+
+        self.context.update(&buf[..amount]);
+        self.size += amount as u64;
+
+        Ok(amount)
+    }
+}
+
+/// Hashes everything written to it, for data that's made by writing it somewhere
+pub struct HashingWriter {
+    context: Context,
+    size: u64,
+}
+
+impl HashingWriter {
+    pub fn new() -> HashingWriter {
+        HashingWriter {
+            context: Context::new(&SHA256),
+            size: 0,
+        }
+    }
+
+    /// The hex encoded SHA-256 and the amount of bytes of everything that was written
+    pub fn finish(self) -> (String, u64) {
+        (to_hex(self.context), self.size)
+    }
+}
+
+impl Write for HashingWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.context.update(buf);
+        self.size += buf.len() as u64;
+
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+fn to_hex(context: Context) -> String {
+    context
+        .finish()
+        .as_ref()
+        .iter()
+        .map(|v| format!("{:02x}", v))
+        .collect()
+}
+
+/// Reads everything from `reader`, returning its hex encoded SHA-256 and size
+pub fn sha256_reader(reader: &mut dyn Read) -> io::Result<(String, u64)> {
+    let mut reader = HashingReader::new(reader);
+    io::copy(&mut reader, &mut io::sink())?;
+
+    Ok(reader.finish())
+}
@@ -1,6 +1,16 @@
+#[allow(clippy::module_inception)]
 mod backup;
+mod backup_chain;
 mod backup_reader;
 mod backup_writer;
+mod chunk_store;
+mod content_chunker;
+mod encryption;
+mod manifest;
 
 pub use backup::*;
+pub use backup_chain::*;
 pub use backup_reader::BackupReader;
+pub use chunk_store::*;
+pub use encryption::*;
+pub use manifest::*;
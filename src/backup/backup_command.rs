@@ -1,5 +1,6 @@
-use crate::backup::Backup;
-use crate::utils::BackupsFolder;
+use crate::backup::{Backup, Compression, CompressionPolicy, FullBackupPolicy};
+use crate::config::Config;
+use crate::utils::{parse_duration, parse_size, BackupsFolder, IgnoreRules};
 use crate::Command;
 use anyhow::{Error, Result};
 use chrono::{Datelike, Timelike, Utc};
@@ -13,44 +14,204 @@ pub enum BackupType {
     Partial,
 }
 
+impl BackupType {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            BackupType::Full => "full",
+            BackupType::Partial => "partial",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Storage {
+    /// Each backup is a zip file that builds upon the previous backup
+    Zip,
+    /// The files are split into chunks shared by every chunked backup
+    Chunks,
+}
+
+impl Storage {
+    pub fn parse(name: &str) -> Result<Storage> {
+        match name {
+            "zip" => Ok(Storage::Zip),
+            "chunks" => Ok(Storage::Chunks),
+            _ => Err(Error::msg(format!(
+                "The storage must be `zip` or `chunks`, got `{}`",
+                name
+            ))),
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Storage::Zip => "zip",
+            Storage::Chunks => "chunks",
+        }
+    }
+}
+
 pub struct BackupArgs {
     pub name: String,
     pub backup_type: BackupType,
+    /// Hash every file to find out if it changed, instead of trusting unchanged sizes and modification times
+    pub paranoid: bool,
+    pub description: Option<String>,
+    pub tags: Vec<String>,
+    pub storage: Storage,
+    pub full_policy: FullBackupPolicy,
+    pub compression: CompressionPolicy,
+    /// Gitignore lines deciding which files and folders to leave out of the backup, from `.backupignore`, the config, `--exclude` and `--include` in that order
+    pub ignore: Vec<String>,
+}
+
+/// Names the backup after the time it was made
+pub fn default_backup_name() -> String {
+    let t = Utc::now();
+
+    format!(
+        "{}-{}-{}_{}-{}-{}",
+        t.year(),
+        t.month(),
+        t.day(),
+        t.hour(),
+        t.minute(),
+        t.second()
+    )
 }
 
 impl Command<'_> for BackupCommand {
     type ArgsType = BackupArgs;
 
     fn parse_args(args: ArgMatches) -> Result<Self::ArgsType> {
+        let config = &Config::get().backup;
+
+        let backup_type = match args.value_of("type").or(config.backup_type.as_deref()) {
+            Some("full") => BackupType::Full,
+            Some("partial") | None => BackupType::Partial,
+            Some(v) => {
+                return Err(Error::msg(format!(
+                    "The backup type must be `full` or `partial`, got `{}`",
+                    v
+                )))
+            }
+        };
+
+        let storage = match args.value_of("storage").or(config.storage.as_deref()) {
+            Some(v) => Storage::parse(v)?,
+            None => Storage::Zip,
+        };
+
+        // Chunked backups never build upon other backups, so they have no type to choose
+        if let (Some(v), Storage::Chunks) = (args.value_of("type"), storage) {
+            return Err(Error::msg(format!(
+                "--type {} doesn't apply to chunked backups, which never build upon other backups",
+                v
+            )));
+        }
+
+        // Each setting given on the command line overrides the same setting from the config
+        let full_policy = FullBackupPolicy {
+            max_depth: match args.value_of("max-depth") {
+                Some(v) => Some(v.parse().map_err(|_| {
+                    Error::msg(format!("--max-depth must be a whole number: {}", v))
+                })?),
+                None => config.max_depth,
+            },
+            max_age: match args.value_of("max-age") {
+                Some(v) => Some(parse_duration(v)?),
+                None => config.max_age.as_deref().map(parse_duration).transpose()?,
+            },
+            max_size: match args.value_of("max-size") {
+                Some(v) => Some(parse_size(v)?),
+                None => config.max_size.as_deref().map(parse_size).transpose()?,
+            },
+        };
+
+        // Only partial zip backups are ever turned into full backups, so asking for both on the command line is a mistake
+        if let Some(flag) = ["max-depth", "max-age", "max-size"]
+            .iter()
+            .find(|v| args.is_present(v))
+        {
+            if let (_, Storage::Chunks) | (BackupType::Full, _) = (backup_type, storage) {
+                return Err(Error::msg(format!(
+                    "--{} only applies to partial zip backups, but this is a {} backup",
+                    flag,
+                    match storage {
+                        Storage::Chunks => "chunked",
+                        Storage::Zip => backup_type.as_str(),
+                    }
+                )));
+            }
+        }
+
+        let mut ignore = IgnoreRules::read_ignore_file(&Config::get().world_dir())?;
+        ignore.extend(config.ignore.iter().cloned());
+        ignore.extend(args.values_of("exclude").into_iter().flatten().map(|v| v.to_string()));
+        ignore.extend(
+            args.values_of("include")
+                .into_iter()
+                .flatten()
+                .map(|v| format!("!{}", v)),
+        );
+
+        let compress = match args.values_of("compress") {
+            Some(v) => v.map(|v| v.to_string()).collect(),
+            None => config.compress.clone(),
+        };
+
         Ok(BackupArgs {
             name: match args.value_of("name") {
                 Some(v) => v.to_string(),
-                None => {
-                    let t = Utc::now();
-
-                    format!(
-                        "{}-{}-{}_{}-{}-{}",
-                        t.year(),
-                        t.month(),
-                        t.day(),
-                        t.hour(),
-                        t.minute(),
-                        t.second()
-                    )
-                }
+                None => default_backup_name(),
             } + ".zip",
-            backup_type: match args.value_of("type") {
-                Some("full") => BackupType::Full,
-                Some("partial") => BackupType::Partial,
-                _ => BackupType::Partial,
+            backup_type,
+            paranoid: args.is_present("paranoid") || config.paranoid,
+            description: args.value_of("description").map(|v| v.to_string()),
+            tags: match args.values_of("tag") {
+                Some(v) => v.map(|v| v.to_string()).collect(),
+                None => config.tags.clone(),
             },
+            storage,
+            full_policy,
+            compression: CompressionPolicy::new(
+                match args.value_of("compression").or(config.compression.as_deref()) {
+                    Some(v) => Compression::parse(v)?,
+                    None => Compression::default(),
+                },
+                &compress,
+            )?,
+            ignore,
         })
     }
 
-    fn run_command(args: Self::ArgsType) -> Result<()> {
+    fn run_command(mut args: Self::ArgsType) -> Result<()> {
+        let config = Config::get();
         let backups = BackupsFolder::get()?;
         let backups_dir = backups.dir();
-        let mc_dir = backups_dir.parent().unwrap().to_path_buf();
+        let mc_dir = config.world_dir();
+
+        if !mc_dir.is_dir() {
+            return Err(Error::msg(format!(
+                "The world folder `{}` doesn't exist",
+                mc_dir.display()
+            )));
+        }
+
+        if let (Storage::Zip, BackupType::Partial, false) =
+            (args.storage, args.backup_type, args.full_policy.is_empty())
+        {
+            let current = match backups.current_backup()? {
+                Some(v) => Backup::get(v)?,
+                None => None,
+            };
+
+            if let Some(reason) = args.full_policy.check(current)? {
+                println!("Making a full backup because {}", reason);
+
+                args.backup_type = BackupType::Full;
+            }
+        }
 
         println!("Storing the backup in {}", &args.name);
 
@@ -58,12 +219,24 @@ impl Command<'_> for BackupCommand {
             return Err(Error::msg("A backup with this name already exists"));
         }
 
+        config.run_hook("pre-backup", &config.hooks.pre_backup, &args.name, None)?;
+
         println!("Copying and compressing files");
 
-        Backup::create(&mc_dir, backups, &args)?;
+        let result = Backup::create(&mc_dir, backups, &args).map(|_| ());
+
+        if result.is_ok() {
+            println!("Backup completed");
+        }
 
-        println!("Backup completed");
+        // The post-backup hook runs even if the backup failed, it may have to undo what the pre-backup hook did
+        let hook = config.run_hook(
+            "post-backup",
+            &config.hooks.post_backup,
+            &args.name,
+            Some(&result),
+        );
 
-        Ok(())
+        result.and(hook)
     }
 }
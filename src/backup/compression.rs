@@ -0,0 +1,224 @@
+use anyhow::{Error, Result};
+use globset::{Glob, GlobMatcher};
+use std::fmt;
+use std::path::Path;
+use zip::write::FileOptions;
+use zip::CompressionMethod;
+
+use crate::backup::backup::BackupData;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Codec {
+    Stored,
+    Deflate,
+    Bzip2,
+    Zstd,
+}
+
+/// A codec and optionally a level, written like `stored`, `deflate-9` or `zstd-19`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Compression {
+    pub codec: Codec,
+    pub level: Option<i32>,
+}
+
+impl Default for Compression {
+    fn default() -> Compression {
+        Compression {
+            codec: Codec::Deflate,
+            level: None,
+        }
+    }
+}
+
+impl Compression {
+    pub fn parse(spec: &str) -> Result<Compression> {
+        let (name, level) = match spec.split_once('-') {
+            Some((name, level)) => (name, Some(level)),
+            None => (spec, None),
+        };
+
+        let (codec, levels) = match name {
+            "stored" => (Codec::Stored, None),
+            "deflate" => (Codec::Deflate, Some(0..=9)),
+            "bzip2" => (Codec::Bzip2, Some(1..=9)),
+            "zstd" => (Codec::Zstd, Some(-7..=22)),
+            _ => {
+                return Err(Error::msg(format!(
+                    "Unknown compression `{}`, use stored, deflate, bzip2 or zstd",
+                    name
+                )))
+            }
+        };
+
+        let level = match (level, levels) {
+            (None, _) => None,
+            (Some(_), None) => {
+                return Err(Error::msg(format!(
+                    "`{}` doesn't have compression levels",
+                    name
+                )))
+            }
+            (Some(level), Some(levels)) => match level.parse::<i32>() {
+                Ok(level) if levels.contains(&level) => Some(level),
+                _ => {
+                    return Err(Error::msg(format!(
+                        "The compression level of `{}` must be from {} to {}, got `{}`",
+                        name,
+                        levels.start(),
+                        levels.end(),
+                        level
+                    )))
+                }
+            },
+        };
+
+        Ok(Compression { codec, level })
+    }
+
+    pub fn options(&self) -> FileOptions {
+        let method = match self.codec {
+            Codec::Stored => CompressionMethod::Stored,
+            Codec::Deflate => CompressionMethod::Deflated,
+            Codec::Bzip2 => CompressionMethod::Bzip2,
+            Codec::Zstd => CompressionMethod::Zstd,
+        };
+
+        FileOptions::default()
+            .compression_method(method)
+            .compression_level(self.level)
+    }
+}
+
+impl fmt::Display for Compression {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let codec = match self.codec {
+            Codec::Stored => "stored",
+            Codec::Deflate => "deflate",
+            Codec::Bzip2 => "bzip2",
+            Codec::Zstd => "zstd",
+        };
+
+        match self.level {
+            Some(level) => write!(f, "{}-{}", codec, level),
+            None => write!(f, "{}", codec),
+        }
+    }
+}
+
+/// How to compress each file, patterns without a `/` are matched against the file name and the first matching override wins
+#[derive(Debug, Clone, Default)]
+pub struct CompressionPolicy {
+    pub default: Compression,
+    overrides: Vec<(String, GlobMatcher, Compression)>,
+}
+
+impl CompressionPolicy {
+    /// `overrides` are written like `*.mca=stored`
+    pub fn new(default: Compression, overrides: &[impl AsRef<str>]) -> Result<CompressionPolicy> {
+        let mut parsed = Vec::new();
+
+        for item in overrides {
+            let item = item.as_ref();
+
+            let (pattern, spec) = item.rsplit_once('=').ok_or(Error::msg(format!(
+                "Expected a compression override like `*.mca=stored`, got `{}`",
+                item
+            )))?;
+
+            let matcher = Glob::new(pattern)
+                .map_err(|e| Error::msg(format!("Invalid pattern `{}`: {}", pattern, e)))?
+                .compile_matcher();
+
+            parsed.push((pattern.to_string(), matcher, Compression::parse(spec)?));
+        }
+
+        Ok(CompressionPolicy {
+            default,
+            overrides: parsed,
+        })
+    }
+
+    /// The compression recorded in a backup, backups from older versions are all deflate
+    pub fn from_data(data: &BackupData) -> Result<CompressionPolicy> {
+        let default = match &data.compression {
+            Some(v) => Compression::parse(v)?,
+            None => Compression::default(),
+        };
+
+        CompressionPolicy::new(default, &data.compression_overrides)
+    }
+
+    pub fn for_path(&self, path: impl AsRef<Path>) -> Compression {
+        let path = path.as_ref();
+
+        for (pattern, matcher, compression) in &self.overrides {
+            let matches = match pattern.contains('/') {
+                true => matcher.is_match(path),
+                false => path.file_name().map(|v| matcher.is_match(v)) == Some(true),
+            };
+
+            if matches {
+                return *compression;
+            }
+        }
+
+        self.default
+    }
+
+    pub fn options_for(&self, path: impl AsRef<Path>) -> FileOptions {
+        self.for_path(path).options()
+    }
+
+    /// The overrides written the same way they're given to `new`
+    pub fn overrides(&self) -> Vec<String> {
+        self.overrides
+            .iter()
+            .map(|(pattern, _, compression)| format!("{}={}", pattern, compression))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_codecs_and_levels() {
+        assert_eq!(
+            Compression::parse("stored").unwrap(),
+            Compression {
+                codec: Codec::Stored,
+                level: None
+            }
+        );
+        assert_eq!(
+            Compression::parse("deflate-9").unwrap(),
+            Compression {
+                codec: Codec::Deflate,
+                level: Some(9)
+            }
+        );
+        assert_eq!(
+            Compression::parse("zstd--7").unwrap(),
+            Compression {
+                codec: Codec::Zstd,
+                level: Some(-7)
+            }
+        );
+    }
+
+    #[test]
+    fn rejects_unknown_codecs_and_levels() {
+        for spec in [
+            "gzip",
+            "stored-1",
+            "deflate-10",
+            "bzip2-0",
+            "zstd-23",
+            "zstd-fast",
+        ] {
+            assert!(Compression::parse(spec).is_err(), "{}", spec);
+        }
+    }
+}
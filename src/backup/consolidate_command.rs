@@ -0,0 +1,88 @@
+use crate::backup::backup::BackupChain;
+use crate::backup::Backup;
+use crate::utils::BackupsFolder;
+use crate::Command;
+use anyhow::{anyhow, Result};
+use clap::ArgMatches;
+use std::path::PathBuf;
+
+pub struct ConsolidateCommand();
+
+pub struct ConsolidateArgs {
+    path: PathBuf,
+    delete_ancestors: bool,
+}
+
+impl Command<'_> for ConsolidateCommand {
+    type ArgsType = ConsolidateArgs;
+
+    fn parse_args(args: ArgMatches) -> Result<Self::ArgsType> {
+        let backups = BackupsFolder::get()?;
+
+        let path = backups.find_backup(args.value_of("name"))?;
+
+        Ok(ConsolidateArgs {
+            path,
+            delete_ancestors: args.is_present("delete-ancestors"),
+        })
+    }
+
+    fn run_command(args: Self::ArgsType) -> Result<()> {
+        let backups = BackupsFolder::get()?;
+        let backup = Backup::get(&args.path)?.ok_or(anyhow!(
+            "The backup `{}` doesn't exist",
+            args.path.display()
+        ))?;
+
+        if backup.get_data().previous.is_none() {
+            println!(
+                "`{}` doesn't depend on any other backup already",
+                backup.get_name()
+            );
+            return Ok(());
+        }
+
+        // Nearest first, so each one stops being needed once the ones after it are gone
+        let ancestors = BackupChain::new(backup.clone())?
+            .links()
+            .iter()
+            .skip(1)
+            .map(|v| v.backup.clone())
+            .collect::<Vec<_>>();
+
+        println!("Consolidating `{}` into a full backup", backup.get_name());
+
+        backup.consolidate()?;
+
+        println!(
+            "`{}` doesn't depend on any other backup anymore",
+            backup.get_name()
+        );
+
+        if !args.delete_ancestors {
+            return Ok(());
+        }
+
+        for ancestor in ancestors {
+            let name = ancestor.get_name();
+            let dependents = Backup::all_readable(&backups)?
+                .into_iter()
+                .filter(|v| v.depends_on().as_ref() == Some(&name))
+                .map(|v| v.get_name())
+                .collect::<Vec<_>>();
+
+            if !dependents.is_empty() {
+                println!(
+                    "Keeping `{}` and the backups before it, these backups depend on it: {}",
+                    name,
+                    dependents.join(", ")
+                );
+                break;
+            }
+
+            ancestor.delete(&backups, false)?;
+        }
+
+        Ok(())
+    }
+}
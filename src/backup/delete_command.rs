@@ -0,0 +1,52 @@
+use crate::backup::backup::BackupFile;
+use crate::backup::{Backup, KeySource};
+use crate::utils::BackupsFolder;
+use crate::Command;
+use anyhow::{anyhow, Result};
+use clap::ArgMatches;
+use std::path::PathBuf;
+
+pub struct DeleteCommand();
+
+pub struct DeleteArgs {
+    path: PathBuf,
+    fold: bool,
+}
+
+impl Command<'_> for DeleteCommand {
+    type ArgsType = DeleteArgs;
+
+    fn parse_args(args: ArgMatches) -> Result<Self::ArgsType> {
+        let backups = BackupsFolder::get()?;
+
+        let path = backups.find_backup(args.value_of("name"))?;
+
+        Ok(DeleteArgs {
+            path,
+            fold: args.is_present("fold"),
+        })
+    }
+
+    fn run_command(args: Self::ArgsType) -> Result<()> {
+        let backups = BackupsFolder::get()?;
+        let backup = match Backup::get(&args.path) {
+            Ok(v) => v.ok_or(anyhow!(
+                "The backup `{}` doesn't exist",
+                args.path.display()
+            ))?,
+            // Without the key every encrypted backup looks unreadable, so it could be one that others depend on
+            Err(e) if KeySource::is_installed() || !BackupFile::is_encrypted(&args.path)? => {
+                println!(
+                    "`{}` can't be read: {:#}",
+                    args.path.file_name().unwrap().to_string_lossy(),
+                    e
+                );
+
+                return Backup::delete_unreadable(&args.path, &backups);
+            }
+            Err(e) => return Err(e),
+        };
+
+        backup.delete(&backups, args.fold)
+    }
+}
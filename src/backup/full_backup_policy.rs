@@ -0,0 +1,89 @@
+use crate::backup::{Backup, BackupType};
+use crate::utils::format_size;
+use anyhow::Result;
+use chrono::{Duration, Local};
+use std::collections::HashSet;
+use std::fs;
+
+/// When `backup` makes a full backup instead of a partial one, so restoring never has to go through too many backups
+#[derive(Debug, Clone, Default)]
+pub struct FullBackupPolicy {
+    /// The most partial backups in a row
+    pub max_depth: Option<usize>,
+    /// The oldest the full backup that the partial backups build upon can be
+    pub max_age: Option<Duration>,
+    /// The most space the full backup and the partial backups building upon it can take up
+    pub max_size: Option<u64>,
+}
+
+impl FullBackupPolicy {
+    pub fn is_empty(&self) -> bool {
+        self.max_depth.is_none() && self.max_age.is_none() && self.max_size.is_none()
+    }
+
+    /// Returns why the backup after `current` should be a full backup, if it should be
+    pub fn check(&self, current: Option<Backup>) -> Result<Option<String>> {
+        let mut chain = Vec::new();
+        let mut seen = HashSet::new();
+        let mut next = current;
+
+        // Walk back to the most recent full backup
+        while let Some(backup) = next {
+            if !seen.insert(backup.get_name()) || backup.get_data().is_chunked() {
+                break;
+            }
+
+            let is_full =
+                backup.get_data().backup_type.as_deref() == Some(BackupType::Full.as_str());
+
+            next = match is_full {
+                true => None,
+                false => backup.prev()?,
+            };
+
+            chain.push(backup);
+        }
+
+        let base = match chain.last() {
+            Some(v) => v,
+            None => return Ok(None),
+        };
+
+        let depth = chain.len() - 1;
+
+        if let Some(max_depth) = self.max_depth {
+            if depth >= max_depth {
+                return Ok(Some(format!(
+                    "there have been {} partial backups since the last full backup",
+                    depth
+                )));
+            }
+        }
+
+        if let Some(max_age) = self.max_age {
+            if Local::now() - base.created()? > max_age {
+                return Ok(Some(format!(
+                    "`{}`, which the partial backups build upon, is older than the maximum age",
+                    base.get_name()
+                )));
+            }
+        }
+
+        if let Some(max_size) = self.max_size {
+            let mut size = 0;
+
+            for backup in &chain {
+                size += fs::metadata(&backup.get_data().current)?.len();
+            }
+
+            if size >= max_size {
+                return Ok(Some(format!(
+                    "the backups since the last full backup take up {}",
+                    format_size(size)
+                )));
+            }
+        }
+
+        Ok(None)
+    }
+}
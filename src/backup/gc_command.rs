@@ -0,0 +1,91 @@
+use crate::backup::backup::ChunkStore;
+use crate::backup::Backup;
+use crate::utils::{format_size, BackupsFolder};
+use crate::Command;
+use anyhow::{anyhow, Result};
+use clap::ArgMatches;
+use std::collections::HashSet;
+
+pub struct GcCommand();
+
+pub struct GcArgs {
+    pub dry_run: bool,
+}
+
+impl Command<'_> for GcCommand {
+    type ArgsType = GcArgs;
+
+    fn parse_args(args: ArgMatches) -> Result<Self::ArgsType> {
+        Ok(GcArgs {
+            dry_run: args.is_present("dry-run"),
+        })
+    }
+
+    fn run_command(args: Self::ArgsType) -> Result<()> {
+        let backups = BackupsFolder::get()?;
+        let _lock = backups.lock()?;
+
+        let mut manifests = Vec::new();
+        let mut unreadable = Vec::new();
+        let mut readable = Vec::new();
+
+        for file in backups.all_backups()? {
+            match Backup::get(file.path()) {
+                Ok(v) => readable.extend(v),
+                Err(e) => {
+                    unreadable.push(format!("{}: {:#}", file.file_name().to_string_lossy(), e))
+                }
+            }
+        }
+
+        // Any backup that can't be read might refer to chunks, so nothing is removed unless every manifest is readable
+        if !unreadable.is_empty() {
+            return Err(anyhow!(
+                "These backups can't be read, so the chunks they use can't be told apart from unused ones. Delete or repair them to collect garbage:\n{}",
+                unreadable.join("\n")
+            ));
+        }
+
+        for backup in readable {
+            if !backup.get_data().is_chunked() {
+                continue;
+            }
+
+            manifests.push(backup.manifest()?.ok_or(anyhow!(
+                "The chunked backup `{}` doesn't have a manifest, refusing to collect garbage",
+                backup.get_name()
+            ))?);
+        }
+
+        let referenced = manifests
+            .iter()
+            .flat_map(|v| &v.files)
+            .flat_map(|v| &v.chunks)
+            .map(|v| &v[..])
+            .collect::<HashSet<_>>();
+
+        let report = ChunkStore::new(&backups).sweep(&referenced, args.dry_run)?;
+
+        println!(
+            "{} chunks are used by {} chunked backups",
+            report.kept,
+            manifests.len()
+        );
+
+        if args.dry_run {
+            println!(
+                "Would remove {} unused chunks, reclaiming {}",
+                report.removed,
+                format_size(report.reclaimed)
+            );
+        } else {
+            println!(
+                "Removed {} unused chunks, reclaimed {}",
+                report.removed,
+                format_size(report.reclaimed)
+            );
+        }
+
+        Ok(())
+    }
+}
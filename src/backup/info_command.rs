@@ -0,0 +1,195 @@
+use crate::backup::backup::{changed_chunks_target, is_prev_backup_marker, BackupChain};
+use crate::backup::Backup;
+use crate::utils::{format_size, BackupsFolder};
+use crate::Command;
+use anyhow::{anyhow, Result};
+use clap::ArgMatches;
+use std::collections::{BTreeSet, HashSet};
+use std::fs;
+use std::path::PathBuf;
+
+pub struct InfoCommand();
+
+pub struct InfoArgs {
+    path: PathBuf,
+}
+
+impl Command<'_> for InfoCommand {
+    type ArgsType = InfoArgs;
+
+    fn parse_args(args: ArgMatches) -> Result<Self::ArgsType> {
+        let backups = BackupsFolder::get()?;
+
+        let path = backups.find_backup(args.value_of("name"))?;
+
+        Ok(InfoArgs { path })
+    }
+
+    fn run_command(args: Self::ArgsType) -> Result<()> {
+        let backup = Backup::get(&args.path)?.ok_or(anyhow!(
+            "The backup `{}` doesn't exist",
+            args.path.display()
+        ))?;
+
+        let chain = BackupChain::new(backup)?;
+        let head = chain.head();
+
+        let marked = head
+            .entries
+            .keys()
+            .filter(|v| is_prev_backup_marker(v))
+            .count();
+
+        let changed_regions = head
+            .entries
+            .keys()
+            .filter(|v| changed_chunks_target(v).is_some())
+            .count();
+
+        let files = chain.files();
+        let stored_directly = files.len() - marked;
+
+        let mut depends_on = BTreeSet::new();
+        let mut unresolved = Vec::new();
+        let mut logical_size = 0;
+
+        for file in &files {
+            match file.location {
+                Some((link, entry)) => {
+                    logical_size += entry.size;
+
+                    if link != 0 {
+                        depends_on.insert(link);
+                    }
+
+                    // Region files stored as their changed chunks also depend on every earlier version they build upon
+                    let mut at = (link, entry);
+
+                    while at.1.name != file.path {
+                        at = chain.resolve(&file.path, at.0 + 1).unwrap();
+                        depends_on.insert(at.0);
+                    }
+                }
+                None => unresolved.push(&file.path),
+            }
+        }
+
+        // Region files stored as their changed chunks are much smaller than the files they stand for, the manifest has the real sizes
+        if let Some(manifest) = head.backup.manifest()? {
+            logical_size = manifest.files.iter().map(|v| v.size as u64).sum();
+        }
+
+        let physical_size = fs::metadata(&args.path)?.len();
+
+        let data = head.backup.get_data();
+
+        println!("Backup: {}", head.backup.get_name());
+        println!(
+            "Created: {}",
+            head.backup.created()?.format("%Y-%m-%d %H:%M:%S")
+        );
+
+        if let Some(backup_type) = &data.backup_type {
+            println!("Type: {}", backup_type);
+        }
+
+        if let Some(description) = &data.description {
+            println!("Description: {}", description);
+        }
+
+        if !data.tags.is_empty() {
+            println!("Tags: {}", data.tags.join(", "));
+        }
+
+        if let Some(source) = &data.source {
+            println!("Source: {}", source.display());
+        }
+
+        if let Some(host_name) = &data.host_name {
+            println!("Host: {}", host_name);
+        }
+
+        if let Some(compression) = &data.compression {
+            println!("Compression: {}", compression);
+
+            for item in &data.compression_overrides {
+                println!("    {}", item);
+            }
+        }
+
+        if !data.ignore_patterns.is_empty() {
+            println!("Ignored:");
+
+            for pattern in &data.ignore_patterns {
+                println!("    {}", pattern);
+            }
+        }
+
+        if let Some(key) = head.backup.get_reader()?.master_key() {
+            println!("Encrypted: with a {}", key.kind());
+        }
+
+        if let Some(tool_version) = &data.tool_version {
+            println!("Made with version: {}", tool_version);
+        }
+
+        println!(
+            "Previous backup: {}",
+            head.backup
+                .previous_name()
+                .unwrap_or_else(|| "none".to_string())
+        );
+
+        if let Some((store, manifest)) = chain.chunked() {
+            let chunks = manifest
+                .files
+                .iter()
+                .flat_map(|v| &v.chunks)
+                .collect::<HashSet<_>>();
+
+            println!("Storage: chunk store in {}", store.dir().display());
+            println!("Files: {}", manifest.files.len());
+            println!("Chunks: {}", chunks.len());
+        } else {
+            println!("Files stored directly: {}", stored_directly);
+            println!("Files stored in previous backups: {}", marked);
+        }
+
+        if changed_regions != 0 {
+            println!(
+                "Region files stored as only their changed chunks: {}",
+                changed_regions
+            );
+        }
+
+        println!("Logical size: {}", format_size(logical_size));
+        println!("Physical size: {}", format_size(physical_size));
+
+        if depends_on.is_empty() {
+            println!("Depends on: nothing");
+        } else {
+            println!("Depends on:");
+
+            for link in depends_on {
+                println!("    {}", chain.links()[link].backup.get_name());
+            }
+        }
+
+        if let Some(missing) = chain.missing() {
+            println!(
+                "Missing ancestor: {}",
+                missing.file_name().unwrap().to_string_lossy()
+            );
+        }
+
+        if !unresolved.is_empty() {
+            println!("Files that can't be found in any previous backup:");
+
+            for path in unresolved {
+                println!("    {}", path);
+            }
+        }
+
+        Ok(())
+    }
+}
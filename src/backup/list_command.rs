@@ -0,0 +1,270 @@
+use crate::backup::backup::{is_metadata_file, is_prev_backup_marker};
+use crate::backup::backup::{BackupFile, PASSPHRASE_ENV};
+use crate::backup::{Backup, KeySource};
+use crate::utils::{format_size, BackupsFolder};
+use crate::Command;
+use anyhow::{anyhow, Result};
+use chrono::{DateTime, Local};
+use clap::ArgMatches;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::fs::DirEntry;
+
+pub struct ListCommand();
+
+#[derive(Debug, Clone, Copy)]
+pub enum ListFormat {
+    Table,
+    Json,
+}
+
+pub struct ListArgs {
+    pub format: ListFormat,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct ListEntry {
+    pub name: String,
+    pub created: DateTime<Local>,
+    /// None if the backup is encrypted and no key was given
+    #[serde(rename = "type")]
+    pub backup_type: Option<String>,
+    pub size: u64,
+    pub files: Option<usize>,
+    pub previous: Option<String>,
+    pub description: Option<String>,
+    pub tags: Vec<String>,
+    pub encrypted: bool,
+    pub chain_depth: usize,
+    pub current: bool,
+    /// Why the backup couldn't be read, in which case only what the file itself tells is known
+    pub error: Option<String>,
+}
+
+impl Command<'_> for ListCommand {
+    type ArgsType = ListArgs;
+
+    fn parse_args(args: ArgMatches) -> Result<Self::ArgsType> {
+        Ok(ListArgs {
+            format: match args.value_of("format") {
+                Some("json") => ListFormat::Json,
+                _ => ListFormat::Table,
+            },
+        })
+    }
+
+    fn run_command(args: Self::ArgsType) -> Result<()> {
+        let backups = BackupsFolder::get()?;
+        let current = backups
+            .current_backup()?
+            .map(|v| v.file_name().unwrap().to_string_lossy().to_string());
+
+        let mut entries = Vec::new();
+
+        let mut locked = 0;
+        let mut unreadable = Vec::new();
+
+        for file in backups.all_backups()? {
+            let path = file.path();
+
+            // Everything but the file itself is encrypted, so only show that it's there
+            if !KeySource::is_installed() && BackupFile::is_encrypted(&path).unwrap_or(false) {
+                locked += 1;
+
+                entries.push(ListEntry {
+                    encrypted: true,
+                    ..file_entry(&file, current.as_deref())?
+                });
+
+                continue;
+            }
+
+            // One damaged backup shouldn't hide the others
+            match read_entry(&file, current.as_deref()) {
+                Ok(v) => entries.push(v),
+                Err(e) => {
+                    unreadable.push(format!("{}: {:#}", file.file_name().to_string_lossy(), e));
+
+                    entries.push(ListEntry {
+                        error: Some(format!("{:#}", e)),
+                        ..file_entry(&file, current.as_deref())?
+                    });
+                }
+            }
+        }
+
+        entries.sort_by(|a, b| a.created.cmp(&b.created).then(a.name.cmp(&b.name)));
+
+        let previous_of = entries
+            .iter()
+            .map(|v| (v.name.clone(), v.previous.clone()))
+            .collect::<HashMap<_, _>>();
+
+        for entry in entries.iter_mut() {
+            entry.chain_depth = chain_depth(&entry.name, &previous_of);
+        }
+
+        match args.format {
+            ListFormat::Json => println!("{}", serde_json::to_string_pretty(&entries)?),
+            ListFormat::Table => {
+                print_table(&entries);
+
+                if locked != 0 {
+                    println!(
+                        "\n{} of the backups are encrypted, give the key with --key-file, --passphrase-file or {} to see more about them",
+                        locked, PASSPHRASE_ENV
+                    );
+                }
+
+                if !unreadable.is_empty() {
+                    println!("\nThese backups can't be read:");
+
+                    for error in &unreadable {
+                        println!("  {}", error);
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Lists a backup without reading it, from the file alone
+fn file_entry(file: &DirEntry, current: Option<&str>) -> Result<ListEntry> {
+    let name = file.file_name().to_string_lossy().to_string();
+    let metadata = file.metadata()?;
+
+    Ok(ListEntry {
+        created: DateTime::from(metadata.modified()?),
+        backup_type: None,
+        size: metadata.len(),
+        files: None,
+        previous: None,
+        description: None,
+        tags: Vec::new(),
+        encrypted: false,
+        chain_depth: 0,
+        current: current == Some(&name[..]),
+        error: None,
+        name,
+    })
+}
+
+fn read_entry(file: &DirEntry, current: Option<&str>) -> Result<ListEntry> {
+    let path = file.path();
+
+    let backup = Backup::get(&path)?.ok_or(anyhow!(
+        "The backup `{}` disappeared while listing",
+        path.display()
+    ))?;
+
+    let mut reader = backup.get_reader()?;
+    let files = reader.entries()?;
+
+    let name = backup.get_name();
+    let data = backup.get_data();
+
+    Ok(ListEntry {
+        created: backup.created()?,
+        backup_type: Some(match &data.backup_type {
+            _ if data.is_chunked() => "chunked".to_string(),
+            Some(v) => v.clone(),
+            None if files.iter().any(|v| is_prev_backup_marker(&v.name)) => "partial".to_string(),
+            None => "full".to_string(),
+        }),
+        size: file.metadata()?.len(),
+        files: Some(match data.file_count {
+            Some(v) => v as usize,
+            None => files
+                .iter()
+                .filter(|v| !v.is_dir && !is_metadata_file(&v.name))
+                .count(),
+        }),
+        previous: backup.depends_on(),
+        description: data.description.clone(),
+        tags: data.tags.clone(),
+        encrypted: reader.master_key().is_some(),
+        chain_depth: 0,
+        current: current == Some(&name[..]),
+        error: None,
+        name,
+    })
+}
+
+/// Counts how many ancestors a backup has, stopping at missing backups and at cycles
+fn chain_depth(name: &str, previous_of: &HashMap<String, Option<String>>) -> usize {
+    let mut depth = 0;
+    let mut at = name;
+
+    while let Some(Some(prev)) = previous_of.get(at) {
+        depth += 1;
+        at = prev;
+
+        if depth > previous_of.len() {
+            break;
+        }
+    }
+
+    depth
+}
+
+fn print_table(entries: &[ListEntry]) {
+    if entries.is_empty() {
+        println!("There are no backups");
+        return;
+    }
+
+    let rows = entries
+        .iter()
+        .map(|v| {
+            vec![
+                if v.current { "*" } else { "" }.to_string(),
+                v.name.clone(),
+                v.created.format("%Y-%m-%d %H:%M:%S").to_string(),
+                match (&v.backup_type, v.encrypted) {
+                    _ if v.error.is_some() => "(unreadable)".to_string(),
+                    (Some(backup_type), true) => format!("{}, encrypted", backup_type),
+                    (Some(backup_type), false) => backup_type.clone(),
+                    (None, true) => "encrypted".to_string(),
+                    (None, false) => "-".to_string(),
+                },
+                format_size(v.size),
+                v.files.map_or_else(|| "-".to_string(), |v| v.to_string()),
+                v.previous.clone().unwrap_or_else(|| "-".to_string()),
+                v.chain_depth.to_string(),
+            ]
+        })
+        .collect::<Vec<_>>();
+
+    let header = [
+        "", "NAME", "CREATED", "TYPE", "SIZE", "FILES", "PREVIOUS", "DEPTH",
+    ];
+
+    let widths = (0..header.len())
+        .map(|i| {
+            rows.iter()
+                .map(|row| row[i].len())
+                .chain(Some(header[i].len()))
+                .max()
+                .unwrap()
+        })
+        .collect::<Vec<_>>();
+
+    let print_row = |row: &[&str]| {
+        let line = row
+            .iter()
+            .zip(&widths)
+            .map(|(cell, width)| format!("{:width$}", cell, width = width))
+            .collect::<Vec<_>>()
+            .join("  ");
+
+        println!("{}", line.trim_end());
+    };
+
+    print_row(&header);
+
+    for row in &rows {
+        print_row(&row.iter().map(|v| &v[..]).collect::<Vec<_>>());
+    }
+}
@@ -1,7 +1,28 @@
+#[allow(clippy::module_inception)]
 mod backup;
 mod backup_command;
+mod compression;
+mod consolidate_command;
+mod delete_command;
+mod full_backup_policy;
+mod gc_command;
+mod info_command;
+mod list_command;
+mod prune_command;
 mod restore_command;
+mod retention_policy;
+mod verify_command;
 
-pub use backup::Backup;
+pub use backup::{Backup, KeySource};
 pub use backup_command::*;
+pub use compression::*;
+pub use consolidate_command::*;
+pub use delete_command::*;
+pub use full_backup_policy::FullBackupPolicy;
+pub use gc_command::*;
+pub use info_command::*;
+pub use list_command::*;
+pub use prune_command::*;
 pub use restore_command::*;
+pub use retention_policy::RetentionPolicy;
+pub use verify_command::*;
@@ -0,0 +1,199 @@
+use crate::backup::{Backup, RetentionPolicy};
+use crate::config::Config;
+use crate::utils::BackupsFolder;
+use crate::Command;
+use anyhow::{Error, Result};
+use clap::ArgMatches;
+use std::cmp::Reverse;
+use std::collections::{HashMap, HashSet};
+
+pub struct PruneCommand();
+
+/// Backups that aren't kept by any rule but that a kept backup builds upon, `depends_on` maps each backup to the one it takes files from
+fn needed_backups<T>(
+    kept: &HashMap<String, T>,
+    depends_on: &HashMap<String, Option<String>>,
+) -> HashSet<String> {
+    let mut needed = HashSet::new();
+
+    for name in kept.keys() {
+        let mut at = depends_on.get(name).cloned().flatten();
+
+        while let Some(prev) = at {
+            if kept.contains_key(&prev) || !needed.insert(prev.clone()) {
+                break;
+            }
+
+            at = depends_on.get(&prev).cloned().flatten();
+        }
+    }
+
+    needed
+}
+
+pub struct PruneArgs {
+    pub policy: RetentionPolicy,
+    pub dry_run: bool,
+    pub fold: bool,
+}
+
+fn parse_amount(args: &ArgMatches, name: &str) -> Result<usize> {
+    match args.value_of(name) {
+        Some(v) => v
+            .parse()
+            .map_err(|_| Error::msg(format!("--{} must be a whole number: {}", name, v))),
+        None => Ok(0),
+    }
+}
+
+impl Command<'_> for PruneCommand {
+    type ArgsType = PruneArgs;
+
+    fn parse_args(args: ArgMatches) -> Result<Self::ArgsType> {
+        let mut policy = RetentionPolicy {
+            keep_last: parse_amount(&args, "keep-last")?,
+            keep_hourly: parse_amount(&args, "keep-hourly")?,
+            keep_daily: parse_amount(&args, "keep-daily")?,
+            keep_weekly: parse_amount(&args, "keep-weekly")?,
+            keep_monthly: parse_amount(&args, "keep-monthly")?,
+        };
+
+        // The rules from the config are only used when none are given on the command line
+        if policy.is_empty() {
+            let config = &Config::get().retention;
+
+            policy = RetentionPolicy {
+                keep_last: config.keep_last,
+                keep_hourly: config.keep_hourly,
+                keep_daily: config.keep_daily,
+                keep_weekly: config.keep_weekly,
+                keep_monthly: config.keep_monthly,
+            };
+        }
+
+        if policy.is_empty() {
+            return Err(Error::msg(
+                "Refusing to delete every backup, specify at least one --keep-* rule or set one in the [retention] section of mcbackup.toml",
+            ));
+        }
+
+        Ok(PruneArgs {
+            policy,
+            dry_run: args.is_present("dry-run"),
+            fold: args.is_present("fold"),
+        })
+    }
+
+    fn run_command(args: Self::ArgsType) -> Result<()> {
+        let backups = BackupsFolder::get()?;
+        // Backups that can't be read are left alone, the rest are still pruned
+        let all = Backup::all_readable(&backups)?;
+
+        let mut times = Vec::new();
+        for backup in &all {
+            times.push((backup.get_name(), backup.created()?));
+        }
+
+        let mut kept = args.policy.apply(&times);
+
+        if let Some(current) = backups.current_backup()? {
+            kept.entry(current.file_name().unwrap().to_string_lossy().to_string())
+                .or_default()
+                .push("current");
+        }
+
+        let depends_on = all
+            .iter()
+            .map(|v| (v.get_name(), v.depends_on()))
+            .collect::<HashMap<_, _>>();
+
+        let needed = match args.fold {
+            true => HashSet::new(),
+            false => needed_backups(&kept, &depends_on),
+        };
+
+        let mut to_remove = all
+            .iter()
+            .filter(|v| !kept.contains_key(&v.get_name()) && !needed.contains(&v.get_name()))
+            .collect::<Vec<_>>();
+
+        // Remove the newest backups first so that folding moves files forward one step at a time
+        let created = times.iter().cloned().collect::<HashMap<_, _>>();
+        to_remove.sort_by_key(|v| Reverse(created[&v.get_name()]));
+
+        times.sort_by_key(|v| v.1);
+
+        for (name, _) in &times {
+            if let Some(reasons) = kept.get(name) {
+                println!("Keeping `{}` ({})", name, reasons.join(", "));
+            } else if needed.contains(name) {
+                println!("Keeping `{}` (a kept backup depends on it)", name);
+            }
+        }
+
+        if to_remove.is_empty() {
+            println!("Nothing to prune");
+            return Ok(());
+        }
+
+        if args.dry_run {
+            for backup in to_remove {
+                println!("Would remove `{}`", backup.get_name());
+            }
+
+            return Ok(());
+        }
+
+        for backup in to_remove {
+            Backup::get(&backup.get_data().current)?
+                .ok_or(Error::msg(format!(
+                    "The backup `{}` disappeared while pruning",
+                    backup.get_name()
+                )))?
+                .delete(&backups, args.fold)?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chain(links: &[(&str, Option<&str>)]) -> HashMap<String, Option<String>> {
+        links
+            .iter()
+            .map(|(name, prev)| (name.to_string(), prev.map(|v| v.to_string())))
+            .collect()
+    }
+
+    fn kept(names: &[&str]) -> HashMap<String, Vec<&'static str>> {
+        names
+            .iter()
+            .map(|v| (v.to_string(), vec!["last"]))
+            .collect()
+    }
+
+    #[test]
+    fn keeps_the_backups_a_partial_backup_builds_upon() {
+        let depends_on = chain(&[("b1", None), ("b2", Some("b1")), ("b3", Some("b2"))]);
+        let needed = needed_backups(&kept(&["b3"]), &depends_on);
+
+        assert_eq!(needed, ["b1", "b2"].iter().map(|v| v.to_string()).collect());
+    }
+
+    #[test]
+    fn stops_at_full_backups() {
+        // b1 full, b2 partial, b3 full, b4 partial, with the last 2 kept
+        let depends_on = chain(&[
+            ("b1", None),
+            ("b2", Some("b1")),
+            ("b3", None),
+            ("b4", Some("b3")),
+        ]);
+        let needed = needed_backups(&kept(&["b3", "b4"]), &depends_on);
+
+        assert!(needed.is_empty());
+    }
+}
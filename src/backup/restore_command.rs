@@ -1,19 +1,38 @@
-use crate::backup::backup::BackupReader;
-use crate::utils::BackupsFolder;
+use crate::backup::backup::BackupChain;
+use crate::backup::{
+    default_backup_name, Backup, BackupArgs, BackupType, Compression, CompressionPolicy,
+    FullBackupPolicy, Storage,
+};
+use crate::config::{Config, CONFIG_FILE};
+use crate::region::{external_chunk_path, splice_chunks, ChunkSelection, Dimension};
+use crate::utils::{BackupsFolder, IgnoreRules, PathFilter, IGNORE_FILE};
 use crate::Command;
-use anyhow::Error;
-use anyhow::Result;
+use anyhow::{anyhow, Error, Result};
 use clap::ArgMatches;
-use std::ffi::OsStr;
-use std::fs::File;
-use std::io::{Read, Write};
-use std::path::PathBuf;
+use std::collections::{HashMap, HashSet};
 use std::fs;
+use std::path::{Path, PathBuf};
 
 pub struct RestoreCommand();
 
+/// Files that decide how the world is backed up, restoring in place leaves them as they are instead of going back to the backup's copies
+const WORLD_SETTINGS: &[&str] = &[IGNORE_FILE, CONFIG_FILE];
+
+fn is_world_setting(path: &Path) -> bool {
+    WORLD_SETTINGS.iter().any(|v| path == Path::new(v))
+}
+
 pub struct RestoreArgs {
     path: PathBuf,
+    target: Option<PathBuf>,
+    safety_backup: bool,
+    /// How the safety backup is stored and compressed, taken from the config like for `backup`
+    storage: Storage,
+    compression: CompressionPolicy,
+    /// Only restore the files matching these globs, leaving the rest of the world alone
+    paths: Option<PathFilter>,
+    /// Only restore these chunks, splicing them into the world's region files
+    chunks: Option<ChunkSelection>,
 }
 
 impl Command<'_> for RestoreCommand {
@@ -22,74 +41,572 @@ impl Command<'_> for RestoreCommand {
     fn parse_args(args: ArgMatches) -> Result<Self::ArgsType> {
         let backups = BackupsFolder::get()?;
 
-        let path = match args.value_of("name") {
-            Some(v) => backups.join(v),
-            None => match backups.current_backup()? {
-                Some(v) => v,
-                None => match backups.all_backups()?.nth(0) {
-                    Some(_) => return Err(Error::msg("The file marking the most recent backup is missing or invalid, specify which backup you want to restore with --name")),
-                    None => return Err(Error::msg("There are no backups to restore")),
+        let path = backups.find_backup(args.value_of("name"))?;
+        let config = &Config::get().backup;
+
+        Ok(RestoreArgs {
+            path,
+            target: args.value_of("target").map(PathBuf::from),
+            safety_backup: !args.is_present("no-safety-backup"),
+            storage: match &config.storage {
+                Some(v) => Storage::parse(v)?,
+                None => Storage::Zip,
+            },
+            compression: CompressionPolicy::new(
+                match &config.compression {
+                    Some(v) => Compression::parse(v)?,
+                    None => Compression::default(),
                 },
+                &config.compress,
+            )?,
+            paths: match args.values_of("path") {
+                Some(v) => Some(PathFilter::new(&v.collect::<Vec<_>>())?),
+                None => None,
             },
-        }.with_extension("zip");
+            chunks: parse_chunk_selection(&args)?,
+        })
+    }
+
+    fn run_command(args: Self::ArgsType) -> Result<()> {
+        let config = Config::get();
+        let backups_folder = BackupsFolder::get()?;
+        let folder_to_restore_to = config.world_dir();
+
+        let regions = match &args.chunks {
+            Some(chunks) => Some(chunks.regions(&folder_to_restore_to)?),
+            None => None,
+        };
+
+        // Chunks too big for their region file are in `.mcc` files next to it, which are needed to restore them
+        let external = regions
+            .iter()
+            .flatten()
+            .flat_map(|(region, indices)| {
+                indices
+                    .iter()
+                    .filter_map(move |index| external_chunk_path(region, *index))
+            })
+            .collect::<HashSet<_>>();
+
+        let include = |path: &str| match (&regions, &args.paths) {
+            (Some(regions), _) => {
+                regions.contains_key(Path::new(path)) || external.contains(Path::new(path))
+            }
+            (None, Some(filter)) => filter.matches(path),
+            (None, None) => true,
+        };
+
+        if let Some(target) = &args.target {
+            return restore_to_target(&args.path, target, &include);
+        }
+
+        let backup = Backup::get(&args.path)?.ok_or(anyhow!(
+            "The backup `{}` doesn't exist",
+            args.path.display()
+        ))?;
+        let chain = BackupChain::new(backup)?;
+
+        // Fail before deleting anything if the backup can't be fully restored
+        chain.check_complete(&include)?;
+
+        // Files the backup left out on purpose weren't backed up, so they're left alone instead of being removed
+        let ignored = IgnoreRules::new(&chain.head().backup.get_data().ignore_patterns)?;
+
+        // The backups can be in the world under a name that isn't hidden, both paths are resolved so that a symlink or `..` in either doesn't hide it
+        let backups_in_world = fs::canonicalize(backups_folder.dir())?
+            .strip_prefix(fs::canonicalize(&folder_to_restore_to)?)
+            .ok()
+            .map(|v| v.to_path_buf());
+
+        let replaceable = |path: &Path| {
+            !ignored.is_ignored(path, false)
+                && !is_world_setting(path)
+                && !matches!(&backups_in_world, Some(v) if path.starts_with(v))
+        };
+
+        let extract = |path: &str| include(path) && !is_world_setting(Path::new(path));
+
+        let name = chain.head().backup.get_name();
+        config.run_hook("pre-restore", &config.hooks.pre_restore, &name, None)?;
+
+        let restore = || -> Result<()> {
+            if args.safety_backup {
+                let safety_args = BackupArgs {
+                    name: format!("pre-restore_{}.zip", default_backup_name()),
+                    backup_type: BackupType::Partial,
+                    paranoid: false,
+                    description: Some(format!(
+                        "Automatic snapshot of the world before restoring `{}`",
+                        chain.head().backup.get_name()
+                    )),
+                    tags: vec!["pre-restore".to_string()],
+                    storage: args.storage,
+                    full_policy: FullBackupPolicy::default(),
+                    compression: args.compression.clone(),
+                    // Everything the restore replaces has to be in the safety backup, including ignored files
+                    ignore: Vec::new(),
+                };
+
+                if backups_folder.join(&safety_args.name).exists() {
+                    return Err(Error::msg(format!(
+                        "A backup named {} already exists, wait a second and try again",
+                        safety_args.name
+                    )));
+                }
+
+                println!(
+                    "Backing up the world to {} before restoring, use --no-safety-backup to skip this",
+                    safety_args.name
+                );
+
+                // The snapshot isn't what the world will be afterwards, so the next backup shouldn't build upon it
+                let current = backups_folder.current_backup()?;
+
+                Backup::create(&folder_to_restore_to, BackupsFolder::get()?, &safety_args)?;
+
+                backups_folder.set_current_backup(
+                    &current
+                        .and_then(|v| v.file_name().map(|v| v.to_string_lossy().to_string()))
+                        .unwrap_or_default(),
+                )?;
+            }
+
+            // Extract next to the world so that swapping it into place is just renaming files
+            let staging = folder_to_restore_to
+                .join(format!(".restore-staging-{:08x}", rand::random::<u32>()));
+
+            println!("Restoring backup");
+
+            if let Err(e) = chain
+                .extract_to(&staging, &extract)
+                .and_then(|_| verify_restored(&chain, &staging, &extract))
+            {
+                let _ = fs::remove_dir_all(&staging);
+
+                return Err(e.context("The restore failed, the world hasn't been changed"));
+            }
+
+            match (&regions, &args.paths) {
+                (Some(regions), _) => {
+                    println!("Replacing the chunks");
+
+                    let result = restore_chunks(&folder_to_restore_to, &staging, regions);
+                    let _ = fs::remove_dir_all(&staging);
+
+                    result?;
+                }
+                (None, Some(filter)) => {
+                    println!("Replacing the matching files");
 
-        if !path.exists() {
+                    replace_matching(&folder_to_restore_to, &staging, &|v| {
+                        filter.matches(v) && replaceable(v)
+                    })?;
+                }
+                (None, None) if !ignored.is_empty() => {
+                    println!("Replacing the world, except for the files the backup ignored");
+
+                    replace_matching(&folder_to_restore_to, &staging, &replaceable)?;
+                }
+                (None, None) => {
+                    println!("Replacing the world");
+
+                    let backups = match &backups_in_world {
+                        Some(v) => folder_to_restore_to.join(v),
+                        None => backups_folder.dir().to_path_buf(),
+                    };
+
+                    swap_into_place(&folder_to_restore_to, &staging, &backups)?;
+                }
+            }
+
+            println!("Restore completed");
+
+            Ok(())
+        };
+
+        let result = restore();
+
+        // The post-restore hook runs even if the restore failed, it may have to undo what the pre-restore hook did
+        let hook = config.run_hook(
+            "post-restore",
+            &config.hooks.post_restore,
+            &name,
+            Some(&result),
+        );
+
+        result.and(hook)
+    }
+}
+
+/// Restores the backup into a separate directory, leaving the world alone
+fn restore_to_target(path: &Path, target: &Path, include: &dyn Fn(&str) -> bool) -> Result<()> {
+    if target.exists() {
+        if !target.is_dir() {
             return Err(Error::msg(format!(
-                "There's no backup with that name {}",
-                path.file_name().unwrap().to_string_lossy()
+                "The target `{}` isn't a directory",
+                target.display()
             )));
         }
 
-        Ok(RestoreArgs { path: path })
+        if fs::read_dir(target)?.next().is_some() {
+            return Err(Error::msg(format!(
+                "The target `{}` isn't empty",
+                target.display()
+            )));
+        }
     }
 
-    fn run_command(args: Self::ArgsType) -> Result<()> {
-        let mut backup = BackupReader::new(args.path)?.unwrap();
-        let backups_folder = BackupsFolder::get()?;
-        let folder_to_restore_to = backups_folder.parent().unwrap();
+    let backup =
+        Backup::get(path)?.ok_or(anyhow!("The backup `{}` doesn't exist", path.display()))?;
+
+    println!("Restoring backup into {}", target.display());
+
+    let chain = BackupChain::new(backup)?;
+    chain.extract_to(target, include)?;
+    verify_restored(&chain, target, include)?;
+
+    println!("Restore completed");
+
+    Ok(())
+}
+
+fn parse_chunk_selection(args: &ArgMatches) -> Result<Option<ChunkSelection>> {
+    let mut chunks = HashSet::new();
+
+    for (arg, shift) in &[("chunks", 0), ("blocks", 4)] {
+        for area in args.values_of(arg).into_iter().flatten() {
+            chunks.extend(ChunkSelection::parse_area(area, *shift)?);
+        }
+    }
+
+    if chunks.is_empty() {
+        return Ok(None);
+    }
+
+    Ok(Some(ChunkSelection {
+        dimension: match args.value_of("dimension") {
+            Some("nether") => Dimension::Nether,
+            Some("end") => Dimension::End,
+            _ => Dimension::Overworld,
+        },
+        chunks,
+    }))
+}
+
+/// Splices the chunks from the region files extracted to `staging` into the world's region files
+fn restore_chunks(
+    world: &Path,
+    staging: &Path,
+    regions: &HashMap<PathBuf, HashSet<usize>>,
+) -> Result<()> {
+    let mut sorted = regions.iter().collect::<Vec<_>>();
+    sorted.sort_by(|a, b| a.0.cmp(b.0));
+
+    for (region, indices) in sorted {
+        let result = splice_chunks(&world.join(region), &staging.join(region), indices)?;
+
+        println!(
+            "{}: restored {} chunks, removed {} chunks that weren't in the backup",
+            region.display(),
+            result.restored,
+            result.removed
+        );
+    }
+
+    Ok(())
+}
+
+/// Checks the restored files against the hashes stored in the backup, if it has them
+fn verify_restored(chain: &BackupChain, dir: &Path, include: &dyn Fn(&str) -> bool) -> Result<()> {
+    let mut manifest = match chain.head().backup.manifest()? {
+        Some(v) => v,
+        None => return Ok(()),
+    };
 
-        println!("Deleting existing files");
+    manifest.files.retain(|v| include(&v.path));
 
-        for path in fs::read_dir(folder_to_restore_to)? {
-            let path_unwraped = path?.path();
+    let mismatches = manifest.mismatches(dir)?;
 
-            if path_unwraped.file_name() == Some(OsStr::new(".backups")) {
+    if !mismatches.is_empty() {
+        return Err(anyhow!(
+            "These files weren't restored correctly: {}",
+            mismatches.join(", ")
+        ));
+    }
+
+    Ok(())
+}
+
+/// Replaces everything in `world` except the backups and hidden files with the contents of `staging`, putting everything back if that fails
+fn swap_into_place(world: &Path, staging: &Path, backups: &Path) -> Result<()> {
+    let old = world.join(format!(".restore-old-{:08x}", rand::random::<u32>()));
+    fs::create_dir(&old)?;
+
+    let mut moved = Vec::new();
+
+    let mut skip = vec![old.as_path(), staging, backups];
+    let settings = WORLD_SETTINGS
+        .iter()
+        .map(|v| world.join(v))
+        .collect::<Vec<_>>();
+    skip.extend(settings.iter().map(|v| v.as_path()));
+
+    let result = move_children(world, &old, &skip, &mut moved)
+        .and_then(|_| move_children(staging, world, &[], &mut moved));
+
+    if let Err(e) = result {
+        for (from, to) in moved.iter().rev() {
+            let _ = fs::rename(to, from);
+        }
+
+        // These only succeed if everything was moved back
+        remove_if_empty(&old);
+        let _ = fs::remove_dir_all(staging);
+
+        return Err(e.context(
+            "Couldn't replace the world with the restored files, the world has been put back the way it was",
+        ));
+    }
+
+    // Only the folders that were merged into the world's are left in here
+    fs::remove_dir_all(staging)?;
+    fs::remove_dir_all(&old)?;
+
+    Ok(())
+}
+
+/// Moves the files in `staging` into `world`, and moves files in `world` that match the filter but aren't in the backup out of the way, putting everything back if that fails
+fn replace_matching(world: &Path, staging: &Path, filter: &dyn Fn(&Path) -> bool) -> Result<()> {
+    let old = world.join(format!(".restore-old-{:08x}", rand::random::<u32>()));
+
+    let restored = relative_files(staging)?;
+    let replaced = relative_files(world)?
+        .into_iter()
+        .filter(|v| filter(v))
+        .collect::<Vec<_>>();
+
+    let mut moved = Vec::new();
+
+    let result = replaced
+        .iter()
+        .try_for_each(|v| move_path(&world.join(v), &old.join(v), &mut moved))
+        .and_then(|_| {
+            restored
+                .iter()
+                .try_for_each(|v| move_path(&staging.join(v), &world.join(v), &mut moved))
+        });
+
+    if let Err(e) = result {
+        let put_back = moved
+            .iter()
+            .rev()
+            .all(|(from, to)| fs::rename(to, from).is_ok());
+
+        if put_back {
+            let _ = fs::remove_dir_all(&old);
+            let _ = fs::remove_dir_all(staging);
+        }
+
+        return Err(e.context(
+            "Couldn't replace the matching files with the restored ones, they have been put back the way they were",
+        ));
+    }
+
+    let restored_set = restored.iter().collect::<HashSet<_>>();
+    let removed = replaced
+        .iter()
+        .filter(|v| !restored_set.contains(v))
+        .count();
+
+    println!(
+        "Restored {} files, removed {} files that weren't in the backup",
+        restored.len(),
+        removed
+    );
+
+    fs::remove_dir_all(staging)?;
+
+    if old.exists() {
+        fs::remove_dir_all(&old)?;
+    }
+
+    Ok(())
+}
+
+/// Every file in `dir` relative to it, skipping hidden files the same way backups do
+fn relative_files(dir: &Path) -> Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    let mut dirs = vec![dir.to_path_buf()];
+
+    while let Some(at) = dirs.pop() {
+        for item in fs::read_dir(&at)? {
+            let path = item?.path();
+
+            if path.file_name().unwrap().to_string_lossy().starts_with('.') {
                 continue;
             }
 
-            if path_unwraped.is_dir() {
-                fs::remove_dir_all(path_unwraped)?;
+            if path.is_dir() {
+                dirs.push(path);
             } else {
-                fs::remove_file(path_unwraped)?;
+                files.push(path.strip_prefix(dir)?.to_path_buf());
             }
         }
+    }
+
+    Ok(files)
+}
+
+fn move_path(from: &Path, to: &Path, moved: &mut Vec<(PathBuf, PathBuf)>) -> Result<()> {
+    fs::create_dir_all(to.parent().unwrap())?;
+    fs::rename(from, to)?;
+
+    moved.push((from.to_path_buf(), to.to_path_buf()));
+
+    Ok(())
+}
+
+/// Whether `move_children` leaves the path where it is, which hidden files and folders are since backups never have them
+fn is_kept(path: &Path, skip: &[&Path]) -> bool {
+    path.file_name().unwrap().to_string_lossy().starts_with('.') || skip.contains(&path)
+}
+
+/// Whether anything in the folder is kept, like a repository nested in the world or a hidden file
+fn holds_kept(dir: &Path, skip: &[&Path]) -> Result<bool> {
+    if skip.iter().any(|v| v.starts_with(dir)) {
+        return Ok(true);
+    }
+
+    for item in fs::read_dir(dir)? {
+        let path = item?.path();
+
+        if is_kept(&path, skip)
+            || (fs::symlink_metadata(&path)?.is_dir() && holds_kept(&path, skip)?)
+        {
+            return Ok(true);
+        }
+    }
 
-        let file_names = backup
-            .file_names()
-            .map(|v| v.to_string())
-            .collect::<Vec<String>>();
+    Ok(false)
+}
 
-        println!("Restoring backup");
+/// Moves everything in `from` into `to` except hidden files and folders and the paths in `skip`, recording each move.
+/// Folders holding something that's kept and folders that are already in `to` are moved one item at a time
+fn move_children(
+    from: &Path,
+    to: &Path,
+    skip: &[&Path],
+    moved: &mut Vec<(PathBuf, PathBuf)>,
+) -> Result<()> {
+    for item in fs::read_dir(from)? {
+        let path = item?.path();
 
-        for file_name in file_names {
-            let mut file = backup.get_file(&file_name).unwrap();
+        if is_kept(&path, skip) {
+            continue;
+        }
 
-            let path = folder_to_restore_to.join(&file_name);
+        let destination = to.join(path.file_name().unwrap());
 
-            if file.is_dir() {
-                continue;
+        // Symlinks are moved as they are, never followed
+        let is_dir = fs::symlink_metadata(&path)?.is_dir();
+
+        if is_dir && (destination.is_dir() || holds_kept(&path, skip)?) {
+            if !destination.is_dir() {
+                fs::create_dir(&destination)?;
             }
 
-            fs::create_dir_all(path.parent().unwrap())?;
+            move_children(&path, &destination, skip, moved)?;
+            continue;
+        }
 
-            let mut out = File::create(path)?;
+        fs::rename(&path, &destination)?;
 
-            let mut data = Vec::new();
-            file.read_to_end(&mut data)?;
+        moved.push((path, destination));
+    }
 
-            out.write_all(&data)?;
+    Ok(())
+}
+
+/// Removes the folder if there's nothing but empty folders in it
+fn remove_if_empty(dir: &Path) {
+    if let Ok(items) = fs::read_dir(dir) {
+        for item in items.flatten() {
+            if item.file_type().map(|v| v.is_dir()).unwrap_or(false) {
+                remove_if_empty(&item.path());
+            }
         }
+    }
+
+    let _ = fs::remove_dir(dir);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::TempDir;
+
+    fn write(path: &Path, text: &str) {
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, text).unwrap();
+    }
+
+    fn read(path: &Path) -> Option<String> {
+        fs::read_to_string(path).ok()
+    }
+
+    #[test]
+    fn swapping_keeps_a_repository_nested_in_the_world() {
+        let dir = TempDir::new();
+        let world = dir.path();
+        let staging = world.join(".restore-staging");
+        let backups = world.join("sub").join("repo");
+
+        write(&backups.join("backup.zip"), "backup");
+        write(&world.join("sub").join("old"), "old");
+        write(&world.join("level.dat"), "old level");
+        write(&staging.join("sub").join("new"), "new");
+        write(&staging.join("level.dat"), "new level");
+
+        swap_into_place(world, &staging, &backups).unwrap();
+
+        assert_eq!(read(&backups.join("backup.zip")).as_deref(), Some("backup"));
+        assert_eq!(read(&world.join("sub").join("old")), None);
+        assert_eq!(read(&world.join("sub").join("new")).as_deref(), Some("new"));
+        assert_eq!(read(&world.join("level.dat")).as_deref(), Some("new level"));
+        assert!(!staging.exists());
+        assert_eq!(fs::read_dir(world).unwrap().count(), 2);
+    }
+
+    #[test]
+    fn swapping_keeps_hidden_files() {
+        let dir = TempDir::new();
+        let world = dir.path().join("world");
+        let staging = world.join(".restore-staging");
+        let backups = dir.path().join("backups");
+
+        write(&world.join(".hidden"), "hidden");
+        write(&world.join("region").join(".hidden"), "nested");
+        write(&world.join("region").join("r.0.0.mca"), "old region");
+        write(&world.join("data").join("old.dat"), "old");
+        write(&staging.join("region").join("r.0.0.mca"), "new region");
+        write(&staging.join("data").join("new.dat"), "new");
+
+        swap_into_place(&world, &staging, &backups).unwrap();
 
-        Ok(())
+        assert_eq!(read(&world.join(".hidden")).as_deref(), Some("hidden"));
+        assert_eq!(
+            read(&world.join("region").join(".hidden")).as_deref(),
+            Some("nested")
+        );
+        assert_eq!(
+            read(&world.join("region").join("r.0.0.mca")).as_deref(),
+            Some("new region")
+        );
+        assert_eq!(read(&world.join("data").join("old.dat")), None);
+        assert_eq!(
+            read(&world.join("data").join("new.dat")).as_deref(),
+            Some("new")
+        );
+        assert!(!staging.exists());
     }
 }
@@ -0,0 +1,167 @@
+use chrono::{DateTime, Datelike, Local, Timelike};
+use std::cmp::Reverse;
+use std::collections::HashMap;
+
+/// Identifies the period of time a backup was made in
+type Period = fn(&DateTime<Local>) -> (i32, u32, u32, u32);
+
+/// Which backups to keep, evaluated the same way as restic's `forget` policies
+#[derive(Debug, Clone, Default)]
+pub struct RetentionPolicy {
+    pub keep_last: usize,
+    pub keep_hourly: usize,
+    pub keep_daily: usize,
+    pub keep_weekly: usize,
+    pub keep_monthly: usize,
+}
+
+impl RetentionPolicy {
+    pub fn is_empty(&self) -> bool {
+        self.keep_last == 0
+            && self.keep_hourly == 0
+            && self.keep_daily == 0
+            && self.keep_weekly == 0
+            && self.keep_monthly == 0
+    }
+
+    /// Decides which of the given backups to keep and returns the reasons each one was kept for
+    pub fn apply(
+        &self,
+        backups: &[(String, DateTime<Local>)],
+    ) -> HashMap<String, Vec<&'static str>> {
+        let mut sorted = backups.iter().collect::<Vec<_>>();
+        sorted.sort_by_key(|v| Reverse(v.1));
+
+        let rules: [(&'static str, usize, Period); 5] = [
+            ("last", self.keep_last, |_| (0, 0, 0, 0)),
+            ("hourly", self.keep_hourly, |t| {
+                (t.year(), t.month(), t.day(), t.hour())
+            }),
+            ("daily", self.keep_daily, |t| {
+                (t.year(), t.month(), t.day(), 0)
+            }),
+            ("weekly", self.keep_weekly, |t| {
+                (t.iso_week().year(), t.iso_week().week(), 0, 0)
+            }),
+            ("monthly", self.keep_monthly, |t| {
+                (t.year(), t.month(), 0, 0)
+            }),
+        ];
+
+        let mut kept: HashMap<String, Vec<&'static str>> = HashMap::new();
+
+        for (reason, amount, period) in rules.iter() {
+            let mut remaining = *amount;
+            let mut last_period = None;
+
+            for (name, time) in &sorted {
+                if remaining == 0 {
+                    break;
+                }
+
+                // `last` keeps every backup, the other rules keep the newest backup in each period
+                let current_period = period(time);
+
+                if *reason != "last" && last_period == Some(current_period) {
+                    continue;
+                }
+
+                last_period = Some(current_period);
+                remaining -= 1;
+
+                kept.entry(name.clone()).or_default().push(reason);
+            }
+        }
+
+        kept
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{NaiveDate, TimeZone};
+
+    fn at(month: u32, day: u32, hour: u32) -> DateTime<Local> {
+        Local
+            .from_local_datetime(
+                &NaiveDate::from_ymd_opt(2021, month, day)
+                    .unwrap()
+                    .and_hms_opt(hour, 0, 0)
+                    .unwrap(),
+            )
+            .unwrap()
+    }
+
+    fn backups(times: &[(&str, DateTime<Local>)]) -> Vec<(String, DateTime<Local>)> {
+        times
+            .iter()
+            .map(|(name, time)| (name.to_string(), *time))
+            .collect()
+    }
+
+    fn names(kept: &HashMap<String, Vec<&'static str>>) -> Vec<String> {
+        let mut names = kept.keys().cloned().collect::<Vec<_>>();
+        names.sort();
+        names
+    }
+
+    #[test]
+    fn keeps_the_newest_backups() {
+        let policy = RetentionPolicy {
+            keep_last: 2,
+            ..Default::default()
+        };
+        let kept = policy.apply(&backups(&[
+            ("a", at(1, 1, 1)),
+            ("c", at(1, 1, 3)),
+            ("b", at(1, 1, 2)),
+        ]));
+
+        assert_eq!(names(&kept), ["b", "c"]);
+    }
+
+    #[test]
+    fn keeps_the_newest_backup_of_each_period() {
+        let policy = RetentionPolicy {
+            keep_daily: 2,
+            ..Default::default()
+        };
+        let kept = policy.apply(&backups(&[
+            ("1-morning", at(1, 1, 8)),
+            ("1-evening", at(1, 1, 20)),
+            ("2-morning", at(1, 2, 8)),
+            ("3-morning", at(1, 3, 8)),
+            ("3-evening", at(1, 3, 20)),
+        ]));
+
+        assert_eq!(names(&kept), ["2-morning", "3-evening"]);
+    }
+
+    #[test]
+    fn records_every_reason() {
+        let policy = RetentionPolicy {
+            keep_last: 1,
+            keep_hourly: 2,
+            keep_monthly: 2,
+            ..Default::default()
+        };
+        let kept = policy.apply(&backups(&[
+            ("january", at(1, 10, 12)),
+            ("february", at(2, 10, 12)),
+            ("march", at(3, 10, 12)),
+        ]));
+
+        assert_eq!(kept["march"], ["last", "hourly", "monthly"]);
+        assert_eq!(kept["february"], ["hourly", "monthly"]);
+        assert!(!kept.contains_key("january"));
+    }
+
+    #[test]
+    fn empty_policies_keep_nothing() {
+        let policy = RetentionPolicy::default();
+
+        assert!(policy.is_empty());
+        assert!(policy.apply(&backups(&[("a", at(1, 1, 1))])).is_empty());
+    }
+}
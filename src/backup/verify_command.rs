@@ -0,0 +1,224 @@
+use crate::backup::backup::{
+    changed_chunks_entry, prev_backup_marker, sha256_reader, HashingWriter, ARCHIVE_DATA_FILE,
+    MANIFEST_FILE,
+};
+use crate::backup::backup::{BackupChain, BackupReader};
+use crate::backup::Backup;
+use crate::utils::BackupsFolder;
+use crate::Command;
+use anyhow::{Error, Result};
+use clap::ArgMatches;
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+
+pub struct VerifyCommand();
+
+pub struct VerifyArgs {
+    paths: Vec<PathBuf>,
+}
+
+impl Command<'_> for VerifyCommand {
+    type ArgsType = VerifyArgs;
+
+    fn parse_args(args: ArgMatches) -> Result<Self::ArgsType> {
+        let backups = BackupsFolder::get()?;
+
+        let mut paths = match args.value_of("name") {
+            Some(name) => vec![backups.find_backup(Some(name))?],
+            None => backups.all_backups()?.map(|v| v.path()).collect(),
+        };
+
+        paths.sort();
+
+        Ok(VerifyArgs { paths })
+    }
+
+    fn run_command(args: Self::ArgsType) -> Result<()> {
+        let mut broken = 0;
+
+        for path in &args.paths {
+            let name = path.file_name().unwrap().to_string_lossy();
+            let problems = verify_backup(path);
+
+            if problems.is_empty() {
+                println!("OK      {}", name);
+            } else {
+                broken += 1;
+
+                println!("BROKEN  {}", name);
+
+                for problem in problems {
+                    println!("    {}", problem);
+                }
+            }
+        }
+
+        if broken != 0 {
+            return Err(Error::msg(format!(
+                "{} of {} backups failed verification",
+                broken,
+                args.paths.len()
+            )));
+        }
+
+        println!("All {} backups verified", args.paths.len());
+
+        Ok(())
+    }
+}
+
+/// Checks that a backup can be fully restored, returning a description of everything that's wrong with it
+fn verify_backup(path: &Path) -> Vec<String> {
+    let mut problems = Vec::new();
+
+    let mut reader = match BackupReader::new(path) {
+        Ok(Some(v)) => v,
+        Ok(None) => return vec!["The backup doesn't exist".to_string()],
+        Err(e) => return vec![format!("The backup can't be opened: {}", e)],
+    };
+
+    for (name, e) in reader.corrupt_entries() {
+        problems.push(format!("Corrupt entry `{}`: {}", name, e));
+    }
+
+    if !reader.file_names().any(|v| v == ARCHIVE_DATA_FILE) {
+        problems.push(format!("{} is missing", ARCHIVE_DATA_FILE));
+        return problems;
+    }
+
+    let backup = match Backup::get(path) {
+        Ok(Some(v)) => v,
+        Ok(None) => return problems,
+        Err(e) => {
+            problems.push(format!("{} is invalid: {}", ARCHIVE_DATA_FILE, e));
+            return problems;
+        }
+    };
+
+    if backup.get_data().current.file_name() != path.file_name() {
+        problems.push(format!(
+            "{} says this backup is named `{}`",
+            ARCHIVE_DATA_FILE,
+            backup.get_data().current.display()
+        ));
+    }
+
+    if let (Some(previous), Some(_)) = (&backup.get_data().previous, backup.depends_on()) {
+        match backup.prev() {
+            Ok(Some(_)) => {}
+            Ok(None) => problems.push(format!(
+                "The previous backup `{}` is missing",
+                previous.display()
+            )),
+            Err(e) => problems.push(format!(
+                "The previous backup `{}` can't be read: {}",
+                previous.display(),
+                e
+            )),
+        }
+    }
+
+    let chain = match BackupChain::new(backup.clone()) {
+        Ok(v) => v,
+        Err(e) => {
+            problems.push(format!("The previous backups can't be read: {}", e));
+            return problems;
+        }
+    };
+
+    for file in chain.files() {
+        if file.location.is_none() {
+            problems.push(format!(
+                "`{}` is stored in a previous backup, but no previous backup contains it",
+                file.path
+            ));
+        }
+    }
+
+    if let Some((store, manifest)) = chain.chunked() {
+        for entry in &manifest.files {
+            match sha256_reader(&mut store.open(&entry.chunks)) {
+                Ok((hash, _)) if hash != entry.hash => problems.push(format!(
+                    "`{}` doesn't match the hash in {}",
+                    entry.path, MANIFEST_FILE
+                )),
+                Ok(_) => {}
+                Err(e) => problems.push(format!(
+                    "`{}` can't be read from the chunk store: {}",
+                    entry.path, e
+                )),
+            }
+        }
+
+        return problems;
+    }
+
+    match backup.manifest() {
+        Ok(Some(manifest)) => {
+            let names = reader
+                .file_names()
+                .map(|v| v.to_string())
+                .collect::<HashSet<_>>();
+
+            for entry in &manifest.files {
+                if names.contains(changed_chunks_entry(&entry.path).to_str().unwrap()) {
+                    // Only the changed chunks are stored, so rebuild the region to check it, regions that can't be found were already reported
+                    let rebuilt = chain.open_region(&entry.path, 0).and_then(|v| match v {
+                        Some(mut region) => {
+                            let mut hasher = HashingWriter::new();
+                            region.write_to(&mut hasher)?;
+
+                            Ok(Some(hasher.finish().0))
+                        }
+                        None => Ok(None),
+                    });
+
+                    match rebuilt {
+                        Ok(Some(hash)) if hash != entry.hash => problems.push(format!(
+                            "`{}` doesn't match the hash in {}",
+                            entry.path, MANIFEST_FILE
+                        )),
+                        Ok(_) => {}
+                        Err(e) => problems.push(format!(
+                            "`{}` can't be rebuilt from its changed chunks: {}",
+                            entry.path, e
+                        )),
+                    }
+
+                    continue;
+                }
+
+                if !names.contains(&entry.path) {
+                    if !names.contains(prev_backup_marker(&entry.path).to_str().unwrap()) {
+                        problems.push(format!(
+                            "`{}` is listed in {} but isn't in the backup",
+                            entry.path, MANIFEST_FILE
+                        ));
+                    }
+
+                    continue;
+                }
+
+                // Entries that can't be read were already reported as corrupt
+                let hash = match reader
+                    .get_file(&entry.path)
+                    .and_then(|mut v| Ok(sha256_reader(&mut v)?))
+                {
+                    Ok((hash, _)) => hash,
+                    Err(_) => continue,
+                };
+
+                if hash != entry.hash {
+                    problems.push(format!(
+                        "`{}` doesn't match the hash in {}",
+                        entry.path, MANIFEST_FILE
+                    ));
+                }
+            }
+        }
+        Ok(None) => {}
+        Err(e) => problems.push(format!("{} is invalid: {}", MANIFEST_FILE, e)),
+    }
+
+    problems
+}
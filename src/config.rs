@@ -0,0 +1,200 @@
+use anyhow::{anyhow, Error, Result};
+use serde::Deserialize;
+use std::env::current_dir;
+use std::fs;
+use std::path::PathBuf;
+use std::process;
+use std::sync::OnceLock;
+
+/// The name of the config file that's searched for in the current folder and the folders above it
+pub const CONFIG_FILE: &str = "mcbackup.toml";
+
+static CONFIG: OnceLock<Config> = OnceLock::new();
+
+/// The contents of `mcbackup.toml`, every value can be overridden by the matching command line flag
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct Config {
+    /// The folder the config file is in, paths in the config are relative to it
+    #[serde(skip)]
+    dir: PathBuf,
+    /// The folder to back up, the folder the config file is in by default
+    pub world: Option<PathBuf>,
+    /// Where the backups are kept, `.backups` in the world by default
+    pub backups: Option<PathBuf>,
+    pub backup: BackupConfig,
+    pub retention: RetentionConfig,
+    pub hooks: HooksConfig,
+}
+
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct BackupConfig {
+    #[serde(rename = "type")]
+    pub backup_type: Option<String>,
+    pub storage: Option<String>,
+    pub paranoid: bool,
+    pub tags: Vec<String>,
+    pub compression: Option<String>,
+    /// Compression overrides like `*.mca=stored`
+    pub compress: Vec<String>,
+    /// Files and folders to leave out of backups, written like the lines of a `.gitignore`. Added after the lines of `.backupignore`
+    pub ignore: Vec<String>,
+    pub max_depth: Option<usize>,
+    pub max_age: Option<String>,
+    pub max_size: Option<String>,
+}
+
+/// Used by `prune` when it's given no `--keep-*` flags
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct RetentionConfig {
+    pub keep_last: usize,
+    pub keep_hourly: usize,
+    pub keep_daily: usize,
+    pub keep_weekly: usize,
+    pub keep_monthly: usize,
+}
+
+/// Shell commands run around backups and restores, like telling a server to stop saving
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct HooksConfig {
+    pub pre_backup: Option<String>,
+    pub post_backup: Option<String>,
+    pub pre_restore: Option<String>,
+    pub post_restore: Option<String>,
+}
+
+impl Config {
+    /// Loads the given config file, or the first `mcbackup.toml` in the current folder or the folders above it
+    pub fn load(path: Option<&str>) -> Result<Config> {
+        let cwd = current_dir()?;
+
+        let path = match path {
+            Some(v) => Some(cwd.join(v)),
+            None => cwd
+                .ancestors()
+                .map(|v| v.join(CONFIG_FILE))
+                .find(|v| v.is_file()),
+        };
+
+        let path = match path {
+            Some(v) => v,
+            None => {
+                return Ok(Config {
+                    dir: cwd,
+                    ..Config::default()
+                })
+            }
+        };
+
+        let text = fs::read_to_string(&path)
+            .map_err(|e| anyhow!("Can't read the config file `{}`: {}", path.display(), e))?;
+
+        let config = toml::from_str::<Config>(&text)
+            .map_err(|e| anyhow!("The config file `{}` is invalid: {}", path.display(), e))?;
+
+        Ok(Config {
+            dir: path.parent().unwrap().to_path_buf(),
+            ..config
+        })
+    }
+
+    /// Overrides the world and backups folders with the ones given on the command line, relative to the current folder
+    pub fn with_paths(self, world: Option<&str>, backups: Option<&str>) -> Result<Config> {
+        let cwd = current_dir()?;
+
+        Ok(Config {
+            world: world.map(|v| cwd.join(v)).or(self.world),
+            backups: backups.map(|v| cwd.join(v)).or(self.backups),
+            ..self
+        })
+    }
+
+    /// Makes the config available to every command
+    pub fn install(self) {
+        let _ = CONFIG.set(self);
+    }
+
+    pub fn get() -> &'static Config {
+        CONFIG.get_or_init(|| Config {
+            dir: current_dir().unwrap_or_default(),
+            ..Config::default()
+        })
+    }
+
+    pub fn world_dir(&self) -> PathBuf {
+        match &self.world {
+            Some(v) => self.dir.join(v),
+            None => self.dir.clone(),
+        }
+    }
+
+    pub fn backups_dir(&self) -> PathBuf {
+        match &self.backups {
+            Some(v) => self.dir.join(v),
+            None => self.world_dir().join(".backups"),
+        }
+    }
+
+    /// Runs a hook in the world folder, failing if it exits unsuccessfully.
+    /// Hooks that run afterwards are given the `outcome` of the backup or restore in `MCBACKUP_OUTCOME`, and the error in `MCBACKUP_ERROR` if it failed
+    pub fn run_hook(
+        &self,
+        name: &str,
+        command: &Option<String>,
+        backup: &str,
+        outcome: Option<&Result<()>>,
+    ) -> Result<()> {
+        let command = match command {
+            Some(v) => v,
+            None => return Ok(()),
+        };
+
+        println!("Running the {} hook", name);
+
+        let mut shell = match cfg!(windows) {
+            true => {
+                let mut shell = process::Command::new("cmd");
+                shell.arg("/C");
+                shell
+            }
+            false => {
+                let mut shell = process::Command::new("sh");
+                shell.arg("-c");
+                shell
+            }
+        };
+
+        shell
+            .arg(command)
+            .current_dir(self.world_dir())
+            .env("MCBACKUP_HOOK", name)
+            .env("MCBACKUP_BACKUP", backup)
+            .env("MCBACKUP_WORLD", self.world_dir())
+            .env("MCBACKUP_BACKUPS", self.backups_dir());
+
+        match outcome {
+            Some(Ok(())) => {
+                shell.env("MCBACKUP_OUTCOME", "success");
+            }
+            Some(Err(e)) => {
+                shell
+                    .env("MCBACKUP_OUTCOME", "failure")
+                    .env("MCBACKUP_ERROR", format!("{:#}", e));
+            }
+            None => {}
+        }
+
+        let status = shell
+            .status()
+            .map_err(|e| anyhow!("Couldn't run the {} hook: {}", name, e))?;
+
+        if !status.success() {
+            return Err(Error::msg(format!("The {} hook failed ({})", name, status)));
+        }
+
+        Ok(())
+    }
+}
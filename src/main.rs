@@ -1,4 +1,6 @@
 mod backup;
+mod config;
+mod region;
 mod root;
 mod subcommand;
 mod utils;
@@ -17,14 +19,73 @@ fn main() -> Result<()> {
         (author: "Xendergo")
         (about: "Manages backups for your minecraft worlds")
         (setting: AppSettings::SubcommandRequiredElseHelp)
+        (@arg world: --world +takes_value +global "The folder to back up and restore into, overriding the config. The current folder by default")
+        (@arg repo: --repo +takes_value +global "The folder the backups are kept in, overriding the config. Can be on another drive or mount, `.backups` in the world by default")
+        (@arg config: --config +takes_value +global "The config file to use instead of the first mcbackup.toml in the current folder or the folders above it")
+        (@arg ("key-file"): --("key-file") +takes_value +global "Encrypt new backups with the key in this file, and decrypt encrypted backups with it. The file must hold at least 32 random bytes")
+        (@arg ("passphrase-file"): --("passphrase-file") +takes_value +global "Encrypt new backups with a key derived from the passphrase in this file, and decrypt encrypted backups with it. The passphrase can also be given in the MCBACKUP_PASSPHRASE environment variable")
+        (@arg ("allow-unencrypted"): --("allow-unencrypted") +global "Read backups that aren't encrypted even though a key is given. Without this they're refused, since anyone who can write to the backups could have replaced an encrypted backup with one")
         (@subcommand backup =>
             (about: "Backup your world")
             (@arg name: -n --name +takes_value "The name of the new backup")
             (@arg type: -t --type +takes_value possible_values(&["full", "partial"]) "whether the backup should take a backup of all the files or only the ones that have changed.\nUsing `partial` doesn't effect the ability to restore data in any way, unless previous backups are altered.")
+            (@arg paranoid: --paranoid "Hash every file to find out if it changed, instead of assuming files with the same size and modification time as in the previous backup are unchanged")
+            (@arg description: -d --description +takes_value "A description to store with the backup")
+            (@arg tag: --tag +takes_value +multiple number_of_values(1) "A tag to store with the backup, can be given multiple times")
+            (@arg ("max-depth"): --("max-depth") +takes_value "Make a full backup instead once there have been this many partial backups since the last full backup")
+            (@arg ("max-age"): --("max-age") +takes_value "Make a full backup instead once the last full backup is older than this, like `12h`, `7d` or `2w`")
+            (@arg ("max-size"): --("max-size") +takes_value "Make a full backup instead once the backups since the last full backup take up this much space, like `500M` or `10G`")
+            (@arg compression: -c --compression +takes_value "How to compress files, `deflate` by default. One of `stored`, `deflate`, `bzip2` or `zstd`, optionally followed by a level like `deflate-9` or `zstd-19`")
+            (@arg compress: --compress +takes_value +multiple number_of_values(1) "Compress the files matching a glob differently, like `*.mca=stored` or `*.{json,dat}=zstd-19`. Globs without a `/` match file names. Can be given multiple times, the first matching one is used")
+            (@arg exclude: --exclude +takes_value +multiple number_of_values(1) "Leave out the files matching this pattern, written like a line of a .gitignore. Added after the patterns in the world's .backupignore and the config. Can be given multiple times")
+            (@arg include: --include +takes_value +multiple number_of_values(1) "Back up the files matching this pattern even if an earlier pattern leaves them out, like `!pattern` in a .gitignore. Can be given multiple times")
+            (@arg storage: -s --storage +takes_value possible_values(&["zip", "chunks"]) "How to store the backup, `zip` by default.\n`zip` stores each backup as a zip file building upon the previous one, `chunks` splits files into chunks that are stored once and shared by every chunked backup, so each chunked backup can be restored or deleted on its own")
         )
         (@subcommand restore =>
             (about: "Restore your world from a previous backup, backing up beforehand is reccommended")
             (@arg name: -n --name +takes_value "The name of the backup to restore, restores the most recent by default")
+            (@arg ("no-safety-backup"): --("no-safety-backup") "Don't back up the world before replacing it with the backup")
+            (@arg path: -p --path +takes_value +multiple number_of_values(1) "Only restore the files matching this glob, like `world/DIM-1/**`, leaving the rest of the world alone. Can be given multiple times")
+            (@arg chunks: --chunks +takes_value +allow_hyphen_values +multiple number_of_values(1) conflicts_with[path target] "Only restore these chunks, given as `x,z` or a box `x1,z1:x2,z2` in chunk coordinates, splicing them into the world's region files. Can be given multiple times")
+            (@arg blocks: --blocks +takes_value +allow_hyphen_values +multiple number_of_values(1) conflicts_with[path target] "Only restore the chunks containing these blocks, given as `x,z` or a box `x1,z1:x2,z2` in block coordinates. Can be given multiple times")
+            (@arg dimension: --dimension +takes_value possible_values(&["overworld", "nether", "end"]) "The dimension of the chunks given with --chunks or --blocks, `overworld` by default")
+            (@arg target: --target +takes_value "An empty directory to restore the backup into instead of replacing the world")
+        )
+        (@subcommand list =>
+            (about: "List every backup, which backup it builds upon, and which one is the most recent")
+            (@arg format: -f --format +takes_value possible_values(&["table", "json"]) "How to print the list of backups, `table` by default")
+        )
+        (@subcommand info =>
+            (about: "Show what a backup contains and which previous backups it depends on")
+            (@arg name: -n --name +takes_value "The name of the backup to inspect, inspects the most recent by default")
+        )
+        (@subcommand delete =>
+            (about: "Delete a backup without breaking the backups that build upon it")
+            (@arg name: -n --name +required +takes_value "The name of the backup to delete")
+            (@arg fold: --fold "Move the files that later backups still need into them instead of refusing to delete the backup")
+        )
+        (@subcommand prune =>
+            (about: "Delete old backups according to retention rules, the most recent backup is always kept")
+            (@arg ("keep-last"): --("keep-last") +takes_value "Keep the n most recent backups")
+            (@arg ("keep-hourly"): --("keep-hourly") +takes_value "Keep the most recent backup of each of the last n hours that have backups")
+            (@arg ("keep-daily"): --("keep-daily") +takes_value "Keep the most recent backup of each of the last n days that have backups")
+            (@arg ("keep-weekly"): --("keep-weekly") +takes_value "Keep the most recent backup of each of the last n weeks that have backups")
+            (@arg ("keep-monthly"): --("keep-monthly") +takes_value "Keep the most recent backup of each of the last n months that have backups")
+            (@arg ("dry-run"): --("dry-run") "Print which backups would be removed without removing them")
+            (@arg fold: --fold "Also remove backups that kept backups depend on, moving the files they need into the kept backups")
+        )
+        (@subcommand verify =>
+            (about: "Check that backups are intact and can be restored")
+            (@arg name: -n --name +takes_value "The name of the backup to verify, verifies every backup by default")
+        )
+        (@subcommand consolidate =>
+            (about: "Turn a partial backup into a full backup that doesn't depend on the backups before it")
+            (@arg name: -n --name +required +takes_value "The name of the backup to consolidate")
+            (@arg ("delete-ancestors"): --("delete-ancestors") "Delete the backups it depended on, unless other backups still depend on them")
+        )
+        (@subcommand gc =>
+            (about: "Free the space taken by chunks that no chunked backup uses anymore")
+            (@arg ("dry-run"): --("dry-run") "Print how much space would be freed without removing anything")
         )
     )
     .get_matches();
@@ -0,0 +1,180 @@
+use anyhow::{Error, Result};
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use super::{chunk_index, region_file_name};
+
+/// The most chunks one area can select, 1024 by 1024 chunks or 16384 by 16384 blocks
+const MAX_AREA_CHUNKS: i64 = 1024 * 1024;
+
+#[derive(Debug, Clone, Copy)]
+pub enum Dimension {
+    Overworld,
+    Nether,
+    End,
+}
+
+impl Dimension {
+    /// Where the dimension's region files are, relative to the world folder
+    fn region_dir(&self) -> PathBuf {
+        match self {
+            Dimension::Overworld => PathBuf::from("region"),
+            Dimension::Nether => Path::new("DIM-1").join("region"),
+            Dimension::End => Path::new("DIM1").join("region"),
+        }
+    }
+}
+
+/// A set of chunks within one dimension
+pub struct ChunkSelection {
+    pub dimension: Dimension,
+    pub chunks: HashSet<(i32, i32)>,
+}
+
+impl ChunkSelection {
+    /// Parses `x,z` or a box `x1,z1:x2,z2`, the coordinates are shifted right by `shift` to turn them into chunk coordinates
+    pub fn parse_area(area: &str, shift: u32) -> Result<Vec<(i32, i32)>> {
+        let parse_point = |point: &str| -> Result<(i32, i32)> {
+            let mut parts = point.split(',').map(|v| v.trim().parse::<i32>());
+
+            match (parts.next(), parts.next(), parts.next()) {
+                (Some(Ok(x)), Some(Ok(z)), None) => Ok((x >> shift, z >> shift)),
+                _ => Err(Error::msg(format!(
+                    "Expected coordinates like `x,z` or `x1,z1:x2,z2`, got `{}`",
+                    area
+                ))),
+            }
+        };
+
+        let (from, to) = match area.split_once(':') {
+            Some((from, to)) => (parse_point(from)?, parse_point(to)?),
+            None => {
+                let point = parse_point(area)?;
+                (point, point)
+            }
+        };
+
+        let count =
+            ((from.0 as i64 - to.0 as i64).abs() + 1) * ((from.1 as i64 - to.1 as i64).abs() + 1);
+
+        // Every chunk is listed, so an area spanning the whole world would never finish
+        if count > MAX_AREA_CHUNKS {
+            return Err(Error::msg(format!(
+                "`{}` covers {} chunks, at most {} can be restored at once",
+                area, count, MAX_AREA_CHUNKS
+            )));
+        }
+
+        let mut chunks = Vec::with_capacity(count as usize);
+
+        for x in from.0.min(to.0)..=from.0.max(to.0) {
+            for z in from.1.min(to.1)..=from.1.max(to.1) {
+                chunks.push((x, z));
+            }
+        }
+
+        Ok(chunks)
+    }
+
+    /// The region files containing the selected chunks relative to `root`, along with the indices of the chunks in them
+    pub fn regions(&self, root: &Path) -> Result<HashMap<PathBuf, HashSet<usize>>> {
+        let region_dir = find_world(root)?.join(self.dimension.region_dir());
+
+        let mut regions: HashMap<PathBuf, HashSet<usize>> = HashMap::new();
+
+        for (x, z) in &self.chunks {
+            regions
+                .entry(region_dir.join(region_file_name(*x, *z)))
+                .or_default()
+                .insert(chunk_index(*x, *z));
+        }
+
+        Ok(regions)
+    }
+}
+
+/// Finds the folder containing `level.dat`, either `root` itself or one of the folders in it
+fn find_world(root: &Path) -> Result<PathBuf> {
+    if root.join("level.dat").is_file() {
+        return Ok(PathBuf::new());
+    }
+
+    let mut worlds = Vec::new();
+
+    for item in fs::read_dir(root)? {
+        let path = item?.path();
+
+        if path.join("level.dat").is_file() {
+            worlds.push(PathBuf::from(path.file_name().unwrap()));
+        }
+    }
+
+    match worlds.len() {
+        1 => Ok(worlds.remove(0)),
+        0 => Err(Error::msg(format!(
+            "Couldn't find a world in {}, there's no level.dat",
+            root.display()
+        ))),
+        _ => Err(Error::msg(format!(
+            "Found more than one world in {}: {}",
+            root.display(),
+            worlds
+                .iter()
+                .map(|v| v.to_string_lossy())
+                .collect::<Vec<_>>()
+                .join(", ")
+        ))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sorted(mut chunks: Vec<(i32, i32)>) -> Vec<(i32, i32)> {
+        chunks.sort_unstable();
+        chunks
+    }
+
+    #[test]
+    fn parses_points_and_boxes() {
+        assert_eq!(ChunkSelection::parse_area("3,-4", 0).unwrap(), [(3, -4)]);
+        assert_eq!(
+            sorted(ChunkSelection::parse_area(" 0, 0 : 1, 1 ", 0).unwrap()),
+            [(0, 0), (0, 1), (1, 0), (1, 1)]
+        );
+    }
+
+    #[test]
+    fn corners_can_be_given_in_any_order() {
+        assert_eq!(
+            sorted(ChunkSelection::parse_area("1,-1:-1,1", 0).unwrap()),
+            sorted(ChunkSelection::parse_area("-1,-1:1,1", 0).unwrap())
+        );
+    }
+
+    #[test]
+    fn shifts_block_coordinates_into_chunks() {
+        assert_eq!(ChunkSelection::parse_area("15,16", 4).unwrap(), [(0, 1)]);
+        assert_eq!(ChunkSelection::parse_area("-1,-17", 4).unwrap(), [(-1, -2)]);
+        assert_eq!(
+            sorted(ChunkSelection::parse_area("0,0:31,15", 4).unwrap()),
+            [(0, 0), (1, 0)]
+        );
+    }
+
+    #[test]
+    fn rejects_bad_coordinates() {
+        for area in ["", "1", "1,2,3", "a,b", "1,2:", "1,2:3", "1;2"] {
+            assert!(ChunkSelection::parse_area(area, 0).is_err(), "{}", area);
+        }
+    }
+
+    #[test]
+    fn rejects_huge_areas() {
+        assert!(ChunkSelection::parse_area("0,0:1023,1023", 0).is_ok());
+        assert!(ChunkSelection::parse_area("0,0:1024,1023", 0).is_err());
+        assert!(ChunkSelection::parse_area("-30000000,-30000000:30000000,30000000", 4).is_err());
+    }
+}
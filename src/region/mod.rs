@@ -0,0 +1,54 @@
+use std::ffi::OsStr;
+use std::path::{Path, PathBuf};
+
+mod chunk_selection;
+mod region_delta;
+mod region_reader;
+mod region_writer;
+mod splice;
+
+pub use chunk_selection::*;
+pub use region_delta::*;
+pub use region_reader::{is_external, RegionReader};
+pub use region_writer::RegionWriter;
+pub use splice::*;
+
+/// Region files are split into sectors of this many bytes
+pub const SECTOR_SIZE: u64 = 4096;
+pub const CHUNKS_PER_REGION: usize = 1024;
+
+/// The position of a chunk within its region file's header
+pub fn chunk_index(chunk_x: i32, chunk_z: i32) -> usize {
+    ((chunk_x & 31) + (chunk_z & 31) * 32) as usize
+}
+
+/// Whether the file is an Anvil region file, going by its name
+pub fn is_region_file(path: impl AsRef<Path>) -> bool {
+    path.as_ref().extension() == Some(OsStr::new("mca"))
+}
+
+/// The name of the region file containing the chunk
+pub fn region_file_name(chunk_x: i32, chunk_z: i32) -> String {
+    format!("r.{}.{}.mca", chunk_x >> 5, chunk_z >> 5)
+}
+
+/// The file holding a chunk that's too big to fit in its region file, which is next to the region file
+fn external_chunk_file_name(chunk_x: i32, chunk_z: i32) -> String {
+    format!("c.{}.{}.mcc", chunk_x, chunk_z)
+}
+
+/// The `.mcc` file that the chunk at `index` of the region file at `region` would be stored in, if the region file is named like one
+pub fn external_chunk_path(region: &Path, index: usize) -> Option<PathBuf> {
+    let name = region.file_name()?.to_str()?;
+    let mut parts = name.strip_prefix("r.")?.strip_suffix(".mca")?.split('.');
+
+    let (region_x, region_z) = match (parts.next(), parts.next(), parts.next()) {
+        (Some(x), Some(z), None) => (x.parse::<i32>().ok()?, z.parse::<i32>().ok()?),
+        _ => return None,
+    };
+
+    Some(region.with_file_name(external_chunk_file_name(
+        region_x * 32 + (index % 32) as i32,
+        region_z * 32 + (index / 32) as i32,
+    )))
+}
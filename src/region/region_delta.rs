@@ -0,0 +1,321 @@
+use anyhow::{Error, Result};
+use std::io::{Read, Seek, SeekFrom, Write};
+
+use super::region_writer::{sector_count, write_padding};
+use super::CHUNKS_PER_REGION;
+
+/// A chunk exactly as it's stored in a region file, along with when it was last saved
+#[derive(Debug, Clone, PartialEq)]
+pub struct Chunk {
+    pub data: Vec<u8>,
+    pub timestamp: u32,
+}
+
+/// Lays the chunks out one after another in a new region file, so the same chunks always give the same file.
+/// `chunk` is asked for one chunk at a time so the region never has to be in memory, every chunk is asked for twice, once to lay out the header and once to write it
+pub fn stream_region(
+    out: &mut dyn Write,
+    chunk: &mut dyn FnMut(usize) -> Result<Option<Chunk>>,
+) -> Result<()> {
+    let mut locations = vec![0; CHUNKS_PER_REGION];
+    let mut timestamps = vec![0; CHUNKS_PER_REGION];
+    let mut next_sector = 2;
+
+    for index in 0..CHUNKS_PER_REGION {
+        if let Some(chunk) = chunk(index)? {
+            let sectors = sector_count(index, chunk.data.len())?;
+
+            locations[index] = ((next_sector as u32) << 8) | sectors as u32;
+            timestamps[index] = chunk.timestamp;
+            next_sector += sectors;
+        }
+    }
+
+    for word in locations.iter().chain(timestamps.iter()) {
+        out.write_all(&word.to_be_bytes())?;
+    }
+
+    for (index, location) in locations.iter().enumerate() {
+        let chunk = chunk(index)?;
+        let sectors = match &chunk {
+            Some(chunk) => sector_count(index, chunk.data.len())?,
+            None => 0,
+        };
+
+        if sectors != (location & 0xff) as u64 {
+            return Err(Error::msg(format!(
+                "Chunk {} changed while the region file was being written",
+                index
+            )));
+        }
+
+        if let Some(chunk) = chunk {
+            out.write_all(&chunk.data)?;
+            write_padding(out, sectors, chunk.data.len())?;
+        }
+    }
+
+    out.flush()?;
+
+    Ok(())
+}
+
+/// Combines the chunks that changed in `own` with the ones that changed in `parent`, which `own` builds upon,
+/// writing the chunks that changed since the version `parent` builds upon
+pub fn compose_deltas<R: Read + Seek>(
+    out: &mut dyn Write,
+    own: &mut RegionDeltaReader<R>,
+    parent: &mut RegionDeltaReader<R>,
+) -> Result<()> {
+    let present = (0..CHUNKS_PER_REGION)
+        .map(|index| own.is_present(index))
+        .collect::<Vec<_>>();
+
+    let mut writer = RegionDeltaWriter::new(out, &present)?;
+
+    for index in (0..CHUNKS_PER_REGION).filter(|v| present[*v]) {
+        let chunk = match own.read_chunk(index)? {
+            Some(v) => Some(v),
+            None => parent.read_chunk(index)?,
+        };
+
+        if let Some(chunk) = chunk {
+            writer.write_chunk(index, &chunk)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Writes the chunks that changed between two versions of a region file, one at a time
+///
+/// Stored as a bitmap of which chunks exist in the new version, followed by the index, timestamp and data of each changed chunk
+pub struct RegionDeltaWriter<W> {
+    inner: W,
+}
+
+impl<W: Write> RegionDeltaWriter<W> {
+    /// `present` is which chunks exist in the new version
+    pub fn new(mut inner: W, present: &[bool]) -> Result<RegionDeltaWriter<W>> {
+        let mut bitmap = vec![0; CHUNKS_PER_REGION / 8];
+
+        for (index, present) in present.iter().enumerate() {
+            if *present {
+                bitmap[index / 8] |= 1 << (index % 8);
+            }
+        }
+
+        inner.write_all(&bitmap)?;
+
+        Ok(RegionDeltaWriter { inner })
+    }
+
+    pub fn write_chunk(&mut self, index: usize, chunk: &Chunk) -> Result<()> {
+        self.inner.write_all(&(index as u16).to_be_bytes())?;
+        self.inner.write_all(&chunk.timestamp.to_be_bytes())?;
+        self.inner.write_all(&chunk.data)?;
+
+        Ok(())
+    }
+}
+
+/// Reads the chunks that changed one at a time, without loading every changed chunk
+pub struct RegionDeltaReader<R> {
+    inner: R,
+    present: Vec<bool>,
+    /// Where each changed chunk's data starts, how long it is and its timestamp
+    changed: Vec<Option<(u64, usize, u32)>>,
+}
+
+impl<R: Read + Seek> RegionDeltaReader<R> {
+    pub fn new(mut inner: R) -> Result<RegionDeltaReader<R>> {
+        let corrupt = || Error::msg("The changed chunks of a region file are corrupt");
+
+        let end = inner.seek(SeekFrom::End(0))?;
+        inner.seek(SeekFrom::Start(0))?;
+
+        let mut bitmap = vec![0; CHUNKS_PER_REGION / 8];
+        inner.read_exact(&mut bitmap).map_err(|_| corrupt())?;
+
+        let present = (0..CHUNKS_PER_REGION)
+            .map(|index| bitmap[index / 8] & (1 << (index % 8)) != 0)
+            .collect();
+
+        let mut changed = vec![None; CHUNKS_PER_REGION];
+        let mut pos = bitmap.len() as u64;
+
+        // Only the headers of the chunks are read, the data is skipped until it's needed
+        while pos < end {
+            let mut header = [0; 10];
+            inner.seek(SeekFrom::Start(pos))?;
+            inner.read_exact(&mut header).map_err(|_| corrupt())?;
+
+            let index = u16::from_be_bytes([header[0], header[1]]) as usize;
+            let timestamp = u32::from_be_bytes([header[2], header[3], header[4], header[5]]);
+            let length = u32::from_be_bytes([header[6], header[7], header[8], header[9]]) as u64;
+
+            pos += header.len() as u64;
+
+            if index >= CHUNKS_PER_REGION || length > end - pos {
+                return Err(corrupt());
+            }
+
+            // The data of a chunk starts with its length
+            changed[index] = Some((pos - 4, 4 + length as usize, timestamp));
+            pos += length;
+        }
+
+        Ok(RegionDeltaReader {
+            inner,
+            present,
+            changed,
+        })
+    }
+
+    /// Whether the chunk exists in the new version
+    pub fn is_present(&self, index: usize) -> bool {
+        self.present[index]
+    }
+
+    /// The chunk if it changed
+    pub fn read_chunk(&mut self, index: usize) -> Result<Option<Chunk>> {
+        let (offset, length, timestamp) = match self.changed[index] {
+            Some(v) => v,
+            None => return Ok(None),
+        };
+
+        let mut data = vec![0; length];
+        self.inner.seek(SeekFrom::Start(offset))?;
+        self.inner.read_exact(&mut data)?;
+
+        Ok(Some(Chunk { data, timestamp }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::region::RegionReader;
+    use std::io::Cursor;
+
+    /// A chunk as it's stored in a region file, its length followed by the compression type and `payload`
+    fn chunk(payload: &[u8], timestamp: u32) -> Chunk {
+        let mut data = (payload.len() as u32 + 1).to_be_bytes().to_vec();
+        data.push(2);
+        data.extend_from_slice(payload);
+
+        Chunk { data, timestamp }
+    }
+
+    fn region(chunks: &[(usize, &[u8], u32)]) -> Vec<Option<Chunk>> {
+        let mut region = vec![None; CHUNKS_PER_REGION];
+
+        for (index, payload, timestamp) in chunks {
+            region[*index] = Some(chunk(payload, *timestamp));
+        }
+
+        region
+    }
+
+    /// The chunks that changed from `old` to `new`, written the way backups write them
+    fn delta(old: &[Option<Chunk>], new: &[Option<Chunk>]) -> Vec<u8> {
+        let present = new.iter().map(|v| v.is_some()).collect::<Vec<_>>();
+        let mut bytes = Vec::new();
+        let mut writer = RegionDeltaWriter::new(&mut bytes, &present).unwrap();
+
+        for (index, (new, old)) in new.iter().zip(old).enumerate() {
+            if let Some(new) = new.as_ref().filter(|v| Some(*v) != old.as_ref()) {
+                writer.write_chunk(index, new).unwrap();
+            }
+        }
+
+        bytes
+    }
+
+    /// Rebuilds the new version of a region from the old one and the chunks that changed
+    fn apply(delta: &[u8], old: &[Option<Chunk>]) -> Vec<Option<Chunk>> {
+        let mut reader = RegionDeltaReader::new(Cursor::new(delta)).unwrap();
+
+        (0..CHUNKS_PER_REGION)
+            .map(|index| match reader.is_present(index) {
+                true => reader
+                    .read_chunk(index)
+                    .unwrap()
+                    .or_else(|| old[index].clone()),
+                false => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn region_round_trip() {
+        let chunks = region(&[(0, b"first", 1), (5, &[7; 5000], 2), (1023, b"last", 3)]);
+
+        let mut bytes = Vec::new();
+        stream_region(&mut bytes, &mut |index| Ok(chunks[index].clone())).unwrap();
+
+        assert_eq!(bytes.len() % 4096, 0);
+
+        let mut reader = RegionReader::new(Cursor::new(bytes)).unwrap();
+
+        for (index, chunk) in chunks.iter().enumerate() {
+            assert_eq!(&reader.chunk(index).unwrap(), chunk);
+        }
+    }
+
+    #[test]
+    fn stream_region_notices_chunks_changing_size() {
+        let mut calls = 0;
+
+        let result = stream_region(&mut Vec::new(), &mut |index| {
+            calls += 1;
+
+            Ok(match index {
+                0 if calls == 1 => Some(chunk(b"small", 1)),
+                0 => Some(chunk(&[1; 5000], 1)),
+                _ => None,
+            })
+        });
+
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn delta_round_trip() {
+        let old = region(&[(0, b"kept", 1), (1, b"changed", 1), (2, b"removed", 1)]);
+        let new = region(&[(0, b"kept", 1), (1, &[9; 3000], 2), (1023, b"added", 2)]);
+        let delta = delta(&old, &new);
+
+        let mut reader = RegionDeltaReader::new(Cursor::new(&delta)).unwrap();
+
+        assert!(reader.is_present(0) && reader.read_chunk(0).unwrap().is_none());
+        assert_eq!(reader.read_chunk(1).unwrap(), new[1]);
+        assert!(!reader.is_present(2));
+        assert_eq!(apply(&delta, &old), new);
+    }
+
+    #[test]
+    fn compose_skips_the_middle_version() {
+        let v1 = region(&[(0, b"a", 1), (1, b"b", 1), (2, b"c", 1)]);
+        let v2 = region(&[(0, b"a2", 2), (1, b"b", 1), (2, b"c", 1), (3, b"d", 2)]);
+        let v3 = region(&[(0, b"a2", 2), (1, b"b3", 3), (3, b"d", 2)]);
+
+        let mut composed = Vec::new();
+        compose_deltas(
+            &mut composed,
+            &mut RegionDeltaReader::new(Cursor::new(delta(&v2, &v3))).unwrap(),
+            &mut RegionDeltaReader::new(Cursor::new(delta(&v1, &v2))).unwrap(),
+        )
+        .unwrap();
+
+        assert_eq!(apply(&composed, &v1), v3);
+    }
+
+    #[test]
+    fn rejects_truncated_deltas() {
+        let bytes = delta(&region(&[]), &region(&[(0, b"added", 1)]));
+
+        assert!(RegionDeltaReader::new(Cursor::new(&bytes[..bytes.len() - 1])).is_err());
+        assert!(RegionDeltaReader::new(Cursor::new(&bytes[..10])).is_err());
+    }
+}
@@ -0,0 +1,100 @@
+use anyhow::{Error, Result};
+use std::io::{Read, Seek, SeekFrom};
+
+use super::{Chunk, CHUNKS_PER_REGION, SECTOR_SIZE};
+
+/// Reads chunks out of an Anvil region file without loading the whole file
+pub struct RegionReader<R> {
+    inner: R,
+    locations: Vec<u32>,
+    timestamps: Vec<u32>,
+}
+
+impl<R: Read + Seek> RegionReader<R> {
+    pub fn new(mut inner: R) -> Result<RegionReader<R>> {
+        let mut header = vec![0; SECTOR_SIZE as usize * 2];
+        inner.seek(SeekFrom::Start(0))?;
+
+        // Empty region files are valid, they just don't have any chunks
+        let read = read_fully(&mut inner, &mut header)?;
+        if read != 0 && read != header.len() {
+            return Err(Error::msg("The region file's header is truncated"));
+        }
+
+        let words = header
+            .chunks(4)
+            .map(|v| u32::from_be_bytes([v[0], v[1], v[2], v[3]]))
+            .collect::<Vec<_>>();
+
+        Ok(RegionReader {
+            inner,
+            locations: words[..CHUNKS_PER_REGION].to_vec(),
+            timestamps: words[CHUNKS_PER_REGION..].to_vec(),
+        })
+    }
+
+    pub fn has_chunk(&self, index: usize) -> bool {
+        self.locations[index] != 0
+    }
+
+    /// When the chunk was last saved, as a unix timestamp in seconds
+    pub fn timestamp(&self, index: usize) -> u32 {
+        self.timestamps[index]
+    }
+
+    /// The chunk along with when it was last saved
+    pub fn chunk(&mut self, index: usize) -> Result<Option<Chunk>> {
+        Ok(self.read_chunk(index)?.map(|data| Chunk {
+            data,
+            timestamp: self.timestamp(index),
+        }))
+    }
+
+    /// The chunk exactly as it's stored in the region file, starting with its length and compression type
+    pub fn read_chunk(&mut self, index: usize) -> Result<Option<Vec<u8>>> {
+        let location = self.locations[index];
+
+        if location == 0 {
+            return Ok(None);
+        }
+
+        let offset = (location >> 8) as u64 * SECTOR_SIZE;
+
+        self.inner.seek(SeekFrom::Start(offset))?;
+
+        let mut length = [0; 4];
+        self.inner.read_exact(&mut length)?;
+        let length = u32::from_be_bytes(length) as usize;
+
+        if length == 0 || length as u64 > (location & 0xff) as u64 * SECTOR_SIZE {
+            return Err(Error::msg(format!(
+                "Chunk {} in the region file has an invalid length",
+                index
+            )));
+        }
+
+        let mut data = vec![0; 4 + length];
+        data[..4].copy_from_slice(&(length as u32).to_be_bytes());
+        self.inner.read_exact(&mut data[4..])?;
+
+        Ok(Some(data))
+    }
+}
+
+/// Whether a chunk returned by `RegionReader::read_chunk` is too big for region files, in which case the region file only says so and the chunk is in a separate `.mcc` file
+pub fn is_external(data: &[u8]) -> bool {
+    data[4] & 0x80 != 0
+}
+
+fn read_fully(reader: &mut impl Read, buf: &mut [u8]) -> Result<usize> {
+    let mut read = 0;
+
+    while read < buf.len() {
+        match reader.read(&mut buf[read..])? {
+            0 => break,
+            amount => read += amount,
+        }
+    }
+
+    Ok(read)
+}
@@ -0,0 +1,73 @@
+use anyhow::{Error, Result};
+use std::io::{Seek, SeekFrom, Write};
+
+use super::{CHUNKS_PER_REGION, SECTOR_SIZE};
+
+/// Writes chunks into a new Anvil region file one after another
+pub struct RegionWriter<W> {
+    inner: W,
+    locations: Vec<u32>,
+    timestamps: Vec<u32>,
+    next_sector: u64,
+}
+
+impl<W: Write + Seek> RegionWriter<W> {
+    pub fn new(mut inner: W) -> Result<RegionWriter<W>> {
+        // Reserve room for the header, it's filled in by `finish`
+        inner.write_all(&vec![0; SECTOR_SIZE as usize * 2])?;
+
+        Ok(RegionWriter {
+            inner,
+            locations: vec![0; CHUNKS_PER_REGION],
+            timestamps: vec![0; CHUNKS_PER_REGION],
+            next_sector: 2,
+        })
+    }
+
+    /// Adds a chunk as returned by `RegionReader::read_chunk`
+    pub fn write_chunk(&mut self, index: usize, data: &[u8], timestamp: u32) -> Result<()> {
+        let sectors = sector_count(index, data.len())?;
+
+        self.inner.write_all(data)?;
+        write_padding(&mut self.inner, sectors, data.len())?;
+
+        self.locations[index] = ((self.next_sector as u32) << 8) | sectors as u32;
+        self.timestamps[index] = timestamp;
+        self.next_sector += sectors;
+
+        Ok(())
+    }
+
+    pub fn finish(mut self) -> Result<W> {
+        self.inner.seek(SeekFrom::Start(0))?;
+
+        for word in self.locations.iter().chain(self.timestamps.iter()) {
+            self.inner.write_all(&word.to_be_bytes())?;
+        }
+
+        self.inner.flush()?;
+
+        Ok(self.inner)
+    }
+}
+
+/// How many sectors a chunk with `len` bytes of data takes up
+pub(super) fn sector_count(index: usize, len: usize) -> Result<u64> {
+    let sectors = (len as u64).div_ceil(SECTOR_SIZE);
+
+    if sectors > 255 {
+        return Err(Error::msg(format!(
+            "Chunk {} is too big to fit in a region file",
+            index
+        )));
+    }
+
+    Ok(sectors)
+}
+
+/// Fills the rest of the last sector of a chunk with `len` bytes of data
+pub(super) fn write_padding(out: &mut dyn Write, sectors: u64, len: usize) -> Result<()> {
+    out.write_all(&vec![0; (sectors * SECTOR_SIZE - len as u64) as usize])?;
+
+    Ok(())
+}
@@ -0,0 +1,296 @@
+use anyhow::{anyhow, Result};
+use std::collections::HashSet;
+use std::fs;
+use std::fs::File;
+use std::io::{BufReader, BufWriter};
+use std::path::Path;
+
+use super::{external_chunk_path, is_external, RegionReader, RegionWriter, CHUNKS_PER_REGION};
+use crate::utils::option_open;
+
+/// How many chunks `splice_chunks` changed
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SpliceResult {
+    pub restored: usize,
+    pub removed: usize,
+}
+
+/// Replaces the chunks at `indices` in the region file at `live` with the ones in the region file at `backup`,
+/// chunks that aren't in the backup are removed so the game generates them again.
+/// The `.mcc` files of replaced chunks that are too big for region files are taken from next to `backup`, every other chunk is left as it is
+pub fn splice_chunks(live: &Path, backup: &Path, indices: &HashSet<usize>) -> Result<SpliceResult> {
+    let mut live_reader = match option_open(live)? {
+        Some(v) => Some(RegionReader::new(BufReader::new(v))?),
+        None => None,
+    };
+    let mut backup_reader = match option_open(backup)? {
+        Some(v) => Some(RegionReader::new(BufReader::new(v))?),
+        None => None,
+    };
+
+    if live_reader.is_none() && backup_reader.is_none() {
+        return Ok(SpliceResult::default());
+    }
+
+    let temp = live.with_file_name(format!(
+        ".{}.tmp",
+        live.file_name().unwrap().to_string_lossy()
+    ));
+
+    fs::create_dir_all(live.parent().unwrap())?;
+
+    let mut result = SpliceResult::default();
+
+    // The `.mcc` files to copy from the backup and the ones that aren't needed anymore
+    let mut external = Vec::new();
+    let mut stale = Vec::new();
+
+    let written = (|| -> Result<()> {
+        let mut writer = RegionWriter::new(BufWriter::new(File::create(&temp)?))?;
+
+        for index in 0..CHUNKS_PER_REGION {
+            let replace = indices.contains(&index);
+
+            let source = if replace {
+                backup_reader.as_mut()
+            } else {
+                live_reader.as_mut()
+            };
+
+            let chunk = match source {
+                Some(reader) => reader
+                    .read_chunk(index)?
+                    .map(|data| (data, reader.timestamp(index))),
+                None => None,
+            };
+
+            if replace {
+                let mcc = external_chunk_path(live, index).ok_or(anyhow!(
+                    "`{}` isn't named like a region file",
+                    live.display()
+                ))?;
+
+                match &chunk {
+                    Some((data, _)) if is_external(data) => {
+                        let from = backup.with_file_name(mcc.file_name().unwrap());
+
+                        if !from.is_file() {
+                            return Err(anyhow!(
+                                "The chunk stored in `{}` can't be restored because the backup doesn't have that file",
+                                mcc.display()
+                            ));
+                        }
+
+                        external.push((from, mcc));
+                    }
+                    _ => stale.push(mcc),
+                }
+            }
+
+            match chunk {
+                Some((data, timestamp)) => {
+                    writer.write_chunk(index, &data, timestamp)?;
+
+                    if replace {
+                        result.restored += 1;
+                    }
+                }
+                None => {
+                    if replace && live_reader.as_ref().map(|v| v.has_chunk(index)) == Some(true) {
+                        result.removed += 1;
+                    }
+                }
+            }
+        }
+
+        writer.finish()?;
+
+        Ok(())
+    })();
+
+    // The `.mcc` files are copied next to their destinations first, so that only renames are left once the region file is in place
+    let mut copies = Vec::new();
+
+    let written = written.and_then(|_| {
+        for (from, to) in &external {
+            let copy = to.with_file_name(format!(
+                ".{}.tmp",
+                to.file_name().unwrap().to_string_lossy()
+            ));
+
+            copies.push((copy, to));
+            fs::copy(from, &copies.last().unwrap().0)?;
+        }
+
+        Ok(fs::rename(&temp, live)?)
+    });
+
+    if let Err(e) = written {
+        let _ = fs::remove_file(&temp);
+
+        for (copy, _) in copies {
+            let _ = fs::remove_file(copy);
+        }
+
+        return Err(e);
+    }
+
+    for (copy, to) in copies {
+        fs::rename(copy, to)?;
+    }
+
+    for path in stale {
+        if path.is_file() {
+            fs::remove_file(path)?;
+        }
+    }
+
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::TempDir;
+    use std::path::PathBuf;
+
+    /// A chunk as it's stored in a region file, its length followed by the compression type and `payload`
+    fn chunk(payload: &[u8]) -> Vec<u8> {
+        let mut data = (payload.len() as u32 + 1).to_be_bytes().to_vec();
+        data.push(2);
+        data.extend_from_slice(payload);
+        data
+    }
+
+    /// A chunk kept in a separate `.mcc` file, which leaves only its header in the region file
+    fn external() -> Vec<u8> {
+        vec![0, 0, 0, 1, 0x82]
+    }
+
+    fn write(path: &Path, chunks: &[(usize, Vec<u8>)]) {
+        let mut writer = RegionWriter::new(File::create(path).unwrap()).unwrap();
+
+        for (index, data) in chunks {
+            writer.write_chunk(*index, data, *index as u32).unwrap();
+        }
+
+        writer.finish().unwrap();
+    }
+
+    fn read(path: &Path) -> Vec<(usize, Vec<u8>)> {
+        let mut reader = RegionReader::new(File::open(path).unwrap()).unwrap();
+
+        (0..CHUNKS_PER_REGION)
+            .filter_map(|index| reader.read_chunk(index).unwrap().map(|v| (index, v)))
+            .collect()
+    }
+
+    /// The world's region folder and the backup's, each with a copy of `r.0.0.mca`
+    fn regions(dir: &TempDir) -> (PathBuf, PathBuf) {
+        let live = dir.path().join("world").join("r.0.0.mca");
+        let backup = dir.path().join("backup").join("r.0.0.mca");
+
+        fs::create_dir_all(live.parent().unwrap()).unwrap();
+        fs::create_dir_all(backup.parent().unwrap()).unwrap();
+
+        (live, backup)
+    }
+
+    #[test]
+    fn replaces_only_the_selected_chunks() {
+        let dir = TempDir::new();
+        let (live, backup) = regions(&dir);
+
+        write(
+            &live,
+            &[
+                (0, chunk(b"live 0")),
+                (1, chunk(b"live 1")),
+                (2, chunk(b"live 2")),
+            ],
+        );
+        write(
+            &backup,
+            &[
+                (0, chunk(b"backup 0")),
+                (1, chunk(&[1; 9000])),
+                (3, chunk(b"backup 3")),
+            ],
+        );
+
+        let result = splice_chunks(&live, &backup, &[1, 2, 3].iter().cloned().collect()).unwrap();
+
+        assert_eq!((result.restored, result.removed), (2, 1));
+        assert_eq!(
+            read(&live),
+            [
+                (0, chunk(b"live 0")),
+                (1, chunk(&[1; 9000])),
+                (3, chunk(b"backup 3"))
+            ]
+        );
+        assert_eq!(
+            RegionReader::new(File::open(&live).unwrap())
+                .unwrap()
+                .timestamp(3),
+            3
+        );
+    }
+
+    #[test]
+    fn creates_missing_region_files() {
+        let dir = TempDir::new();
+        let (live, backup) = regions(&dir);
+
+        write(&backup, &[(5, chunk(b"backup 5")), (6, chunk(b"backup 6"))]);
+
+        splice_chunks(&live, &backup, &[5].iter().cloned().collect()).unwrap();
+
+        assert_eq!(read(&live), [(5, chunk(b"backup 5"))]);
+    }
+
+    #[test]
+    fn copies_the_mcc_files_of_selected_chunks() {
+        let dir = TempDir::new();
+        let (live, backup) = regions(&dir);
+        let mcc = |path: &Path, x: i32| path.with_file_name(format!("c.{}.0.mcc", x));
+
+        write(&live, &[(0, external()), (1, external()), (2, external())]);
+        fs::write(mcc(&live, 0), "live 0").unwrap();
+        fs::write(mcc(&live, 1), "live 1").unwrap();
+        fs::write(mcc(&live, 2), "live 2").unwrap();
+
+        write(
+            &backup,
+            &[(0, external()), (1, external()), (2, chunk(b"backup 2"))],
+        );
+        fs::write(mcc(&backup, 0), "backup 0").unwrap();
+        fs::write(mcc(&backup, 1), "backup 1").unwrap();
+
+        splice_chunks(&live, &backup, &[0, 2].iter().cloned().collect()).unwrap();
+
+        assert_eq!(fs::read_to_string(mcc(&live, 0)).unwrap(), "backup 0");
+        assert_eq!(fs::read_to_string(mcc(&live, 1)).unwrap(), "live 1");
+        assert!(!mcc(&live, 2).exists());
+        assert_eq!(read(&live)[2], (2, chunk(b"backup 2")));
+    }
+
+    #[test]
+    fn fails_for_selected_chunks_whose_mcc_file_is_missing() {
+        let dir = TempDir::new();
+        let (live, backup) = regions(&dir);
+
+        write(&live, &[(0, chunk(b"live 0"))]);
+        write(&backup, &[(0, external()), (1, chunk(b"backup 1"))]);
+
+        assert!(splice_chunks(&live, &backup, &[0].iter().cloned().collect()).is_err());
+        assert_eq!(read(&live), [(0, chunk(b"live 0"))]);
+
+        splice_chunks(&live, &backup, &[1].iter().cloned().collect()).unwrap();
+        assert_eq!(
+            read(&live),
+            [(0, chunk(b"live 0")), (1, chunk(b"backup 1"))]
+        );
+        assert_eq!(fs::read_dir(live.parent().unwrap()).unwrap().count(), 1);
+    }
+}
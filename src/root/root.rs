@@ -1,5 +1,14 @@
 use crate::backup::BackupCommand;
+use crate::backup::ConsolidateCommand;
+use crate::backup::DeleteCommand;
+use crate::backup::GcCommand;
+use crate::backup::InfoCommand;
+use crate::backup::KeySource;
+use crate::backup::ListCommand;
+use crate::backup::PruneCommand;
 use crate::backup::RestoreCommand;
+use crate::backup::VerifyCommand;
+use crate::config::Config;
 use crate::run_command;
 use crate::subcommand::Command;
 use anyhow::Result;
@@ -18,6 +27,19 @@ impl<'a> Command<'a> for Root {
     fn parse_args(args: ArgMatches<'a>) -> Result<Self::ArgsType> {
         let subcommand = args.subcommand.unwrap();
 
+        Config::load(subcommand.matches.value_of("config"))?
+            .with_paths(
+                subcommand.matches.value_of("world"),
+                subcommand.matches.value_of("repo"),
+            )?
+            .install();
+
+        if let Some(key) = KeySource::from_args(&subcommand.matches)? {
+            key.install();
+        }
+
+        KeySource::allow_unencrypted(subcommand.matches.is_present("allow-unencrypted"));
+
         Ok(RootArgs {
             name: subcommand.name,
             matches: subcommand.matches,
@@ -28,6 +50,13 @@ impl<'a> Command<'a> for Root {
         match &args.name[..] {
             "backup" => run_command::<BackupCommand>(args.matches)?,
             "restore" => run_command::<RestoreCommand>(args.matches)?,
+            "list" => run_command::<ListCommand>(args.matches)?,
+            "info" => run_command::<InfoCommand>(args.matches)?,
+            "delete" => run_command::<DeleteCommand>(args.matches)?,
+            "prune" => run_command::<PruneCommand>(args.matches)?,
+            "verify" => run_command::<VerifyCommand>(args.matches)?,
+            "gc" => run_command::<GcCommand>(args.matches)?,
+            "consolidate" => run_command::<ConsolidateCommand>(args.matches)?,
             _ => unreachable!(),
         };
 
@@ -1,31 +1,53 @@
 use anyhow::Error;
 use anyhow::Result;
 use core::ops::Deref;
-use std::env::current_dir;
 use std::ffi::OsStr;
 use std::fs;
 use std::fs::DirEntry;
 use std::fs::File;
+use std::fs::OpenOptions;
 use std::io;
 use std::io::Read;
 use std::io::Write;
 use std::path::Path;
 use std::path::PathBuf;
+use std::process;
 
 use super::option_open;
+use crate::config::Config;
 
 pub struct BackupsFolder {
     dir: PathBuf,
 }
 
+/// Keeps other processes from making backups or collecting garbage at the same time, released when dropped
+pub struct BackupsLock {
+    path: PathBuf,
+}
+
+impl Drop for BackupsLock {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
 impl BackupsFolder {
+    /// The folder set in the config, `.backups` in the world by default
     pub fn get() -> io::Result<BackupsFolder> {
-        let cwd = current_dir()?;
-
-        let backups_folder = cwd.join(".backups");
+        let backups_folder = Config::get().backups_dir();
 
+        // Only the folder itself is created, so a repository on a drive that isn't mounted fails instead of ending up on the wrong disk
         if !backups_folder.is_dir() {
-            fs::create_dir(&backups_folder)?
+            fs::create_dir(&backups_folder).map_err(|e| {
+                io::Error::new(
+                    e.kind(),
+                    format!(
+                        "Can't create the backups folder `{}`: {}",
+                        backups_folder.display(),
+                        e
+                    ),
+                )
+            })?
         }
 
         Ok(BackupsFolder {
@@ -63,16 +85,62 @@ impl BackupsFolder {
 
         let mut file = File::create(current_backup_file)?;
 
-        file.write(name.as_bytes())?;
+        file.write_all(name.as_bytes())?;
 
         Ok(())
     }
 
+    pub fn lock(&self) -> Result<BackupsLock> {
+        let path = self.dir.join(".lock");
+
+        match OpenOptions::new().write(true).create_new(true).open(&path) {
+            Ok(mut file) => {
+                file.write_all(process::id().to_string().as_bytes())?;
+
+                Ok(BackupsLock { path })
+            }
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists => {
+                let owner = fs::read_to_string(&path).unwrap_or_default();
+
+                Err(Error::msg(format!(
+                    "Another backup or gc is running (process {}), if it isn't delete {}",
+                    owner.trim(),
+                    path.display()
+                )))
+            }
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Finds the backup with the given name, or the most recent backup if no name is given
+    pub fn find_backup(&self, name: Option<&str>) -> Result<PathBuf> {
+        let path = match name {
+            Some(v) => self.join(v),
+            None => match self.current_backup()? {
+                Some(v) => v,
+                None => match self.all_backups()?.next() {
+                    Some(_) => return Err(Error::msg("The file marking the most recent backup is missing or invalid, specify which backup you want with --name")),
+                    None => return Err(Error::msg("There are no backups")),
+                },
+            },
+        }
+        .with_extension("zip");
+
+        if !path.exists() {
+            return Err(Error::msg(format!(
+                "There's no backup with that name {}",
+                path.file_name().unwrap().to_string_lossy()
+            )));
+        }
+
+        Ok(path)
+    }
+
     pub fn all_backups(&self) -> io::Result<Box<dyn Iterator<Item = DirEntry>>> {
         Ok(Box::new(
             fs::read_dir(self.dir())?
                 .filter(|file| match file {
-                    Ok(v) => v.path().extension() == Some(OsStr::new(".zip")),
+                    Ok(v) => v.path().is_file() && v.path().extension() == Some(OsStr::new("zip")),
                     Err(_) => false,
                 })
                 .map(|v| v.unwrap()),
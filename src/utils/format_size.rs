@@ -0,0 +1,74 @@
+use anyhow::{Error, Result};
+
+const UNITS: [&str; 5] = ["B", "KiB", "MiB", "GiB", "TiB"];
+
+pub fn format_size(bytes: u64) -> String {
+    let mut size = bytes as f64;
+    let mut unit = 0;
+
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+
+    if unit == 0 {
+        format!("{} {}", bytes, UNITS[unit])
+    } else {
+        format!("{:.1} {}", size, UNITS[unit])
+    }
+}
+
+/// Parses sizes like `500M`, `10GiB` or `1.5g`, every unit is a power of 1024 like in `format_size`
+pub fn parse_size(size: &str) -> Result<u64> {
+    let size = size.trim();
+    let split = size
+        .find(|v: char| !v.is_ascii_digit() && v != '.')
+        .unwrap_or(size.len());
+    let (amount, unit) = size.split_at(split);
+
+    let amount = amount.parse::<f64>().map_err(|_| {
+        Error::msg(format!(
+            "Expected a size like `500M` or `10GiB`, got `{}`",
+            size
+        ))
+    })?;
+
+    let unit = match &unit.trim().to_lowercase()[..] {
+        "" | "b" => 0,
+        "k" | "kb" | "kib" => 1,
+        "m" | "mb" | "mib" => 2,
+        "g" | "gb" | "gib" => 3,
+        "t" | "tb" | "tib" => 4,
+        _ => return Err(Error::msg(format!("Unknown size unit `{}`", unit))),
+    };
+
+    Ok((amount * 1024f64.powi(unit)) as u64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_sizes() {
+        assert_eq!(parse_size("123").unwrap(), 123);
+        assert_eq!(parse_size("4B").unwrap(), 4);
+        assert_eq!(parse_size("500M").unwrap(), 500 * 1024 * 1024);
+        assert_eq!(parse_size("10GiB").unwrap(), 10 * 1024 * 1024 * 1024);
+        assert_eq!(parse_size("1.5g").unwrap(), 1536 * 1024 * 1024);
+        assert_eq!(parse_size("2 kb").unwrap(), 2048);
+    }
+
+    #[test]
+    fn rejects_bad_sizes() {
+        for size in ["", "M", "1x", "1.2.3G"] {
+            assert!(parse_size(size).is_err(), "{}", size);
+        }
+    }
+
+    #[test]
+    fn formats_what_it_parses() {
+        assert_eq!(format_size(parse_size("1023").unwrap()), "1023 B");
+        assert_eq!(format_size(parse_size("1.5M").unwrap()), "1.5 MiB");
+    }
+}
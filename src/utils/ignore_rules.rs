@@ -0,0 +1,59 @@
+use anyhow::{anyhow, Result};
+use ignore::gitignore::{Gitignore, GitignoreBuilder};
+use std::fs;
+use std::io;
+use std::path::Path;
+
+/// The file in the world listing what to leave out of backups, written like a `.gitignore`
+pub const IGNORE_FILE: &str = ".backupignore";
+
+/// Decides which files are left out of backups using gitignore rules, later rules win over earlier ones
+pub struct IgnoreRules {
+    matcher: Gitignore,
+}
+
+impl IgnoreRules {
+    /// `patterns` are lines of a `.gitignore`, paths matched against them are relative to the world
+    pub fn new(patterns: &[impl AsRef<str>]) -> Result<IgnoreRules> {
+        let mut builder = GitignoreBuilder::new("");
+
+        for pattern in patterns {
+            let pattern = pattern.as_ref();
+
+            builder
+                .add_line(None, pattern)
+                .map_err(|e| anyhow!("Invalid ignore pattern `{}`: {}", pattern, e))?;
+        }
+
+        Ok(IgnoreRules {
+            matcher: builder.build()?,
+        })
+    }
+
+    /// The lines of the world's `.backupignore` that are patterns, or nothing if it doesn't have one
+    pub fn read_ignore_file(world: &Path) -> Result<Vec<String>> {
+        let text = match fs::read_to_string(world.join(IGNORE_FILE)) {
+            Ok(v) => v,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(anyhow!("Can't read {}: {}", IGNORE_FILE, e)),
+        };
+
+        Ok(text
+            .lines()
+            .map(|v| v.trim_end())
+            .filter(|v| !v.is_empty() && !v.starts_with('#'))
+            .map(|v| v.to_string())
+            .collect())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.matcher.is_empty()
+    }
+
+    /// Whether the file or folder, or any folder it's in, is ignored
+    pub fn is_ignored(&self, path: impl AsRef<Path>, is_dir: bool) -> bool {
+        self.matcher
+            .matched_path_or_any_parents(path, is_dir)
+            .is_ignore()
+    }
+}
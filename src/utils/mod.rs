@@ -1,8 +1,21 @@
 mod backups_folder;
+mod format_size;
+mod ignore_rules;
 mod option_open;
+mod parse_duration;
+mod path_filter;
+mod temp_file;
 
 pub use backups_folder::*;
+pub use format_size::{format_size, parse_size};
+pub use ignore_rules::{IgnoreRules, IGNORE_FILE};
 pub use option_open::option_open;
+pub use parse_duration::parse_duration;
+pub use path_filter::PathFilter;
+pub use temp_file::TempFile;
+
+#[cfg(test)]
+pub use temp_file::TempDir;
 
 #[macro_export]
 macro_rules! try_option {
@@ -0,0 +1,49 @@
+use anyhow::{Error, Result};
+use chrono::Duration;
+
+/// Parses durations like `30m`, `12h`, `7d` or `2w`
+pub fn parse_duration(duration: &str) -> Result<Duration> {
+    let duration = duration.trim();
+    let split = duration
+        .find(|v: char| !v.is_ascii_digit())
+        .unwrap_or(duration.len());
+    let (amount, unit) = duration.split_at(split);
+
+    let amount = amount.parse::<i64>().map_err(|_| {
+        Error::msg(format!(
+            "Expected a duration like `12h` or `7d`, got `{}`",
+            duration
+        ))
+    })?;
+
+    match unit {
+        "m" => Ok(Duration::minutes(amount)),
+        "h" => Ok(Duration::hours(amount)),
+        "d" => Ok(Duration::days(amount)),
+        "w" => Ok(Duration::weeks(amount)),
+        _ => Err(Error::msg(format!(
+            "Unknown duration unit `{}`, use m, h, d or w",
+            unit
+        ))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_each_unit() {
+        assert_eq!(parse_duration("30m").unwrap(), Duration::minutes(30));
+        assert_eq!(parse_duration("12h").unwrap(), Duration::hours(12));
+        assert_eq!(parse_duration(" 7d ").unwrap(), Duration::days(7));
+        assert_eq!(parse_duration("2w").unwrap(), Duration::weeks(2));
+    }
+
+    #[test]
+    fn rejects_bad_durations() {
+        for duration in ["", "d", "12", "12s", "1.5h", "-3d"] {
+            assert!(parse_duration(duration).is_err(), "{}", duration);
+        }
+    }
+}
@@ -0,0 +1,32 @@
+use anyhow::Result;
+use globset::{GlobBuilder, GlobSet, GlobSetBuilder};
+use std::path::Path;
+
+/// Matches paths against globs, a path also matches if any of the folders it's in matches
+pub struct PathFilter {
+    globs: GlobSet,
+}
+
+impl PathFilter {
+    pub fn new(patterns: &[impl AsRef<str>]) -> Result<PathFilter> {
+        let mut builder = GlobSetBuilder::new();
+
+        for pattern in patterns {
+            builder.add(
+                GlobBuilder::new(pattern.as_ref().trim_end_matches('/'))
+                    .literal_separator(true)
+                    .build()?,
+            );
+        }
+
+        Ok(PathFilter {
+            globs: builder.build()?,
+        })
+    }
+
+    pub fn matches(&self, path: impl AsRef<Path>) -> bool {
+        path.as_ref()
+            .ancestors()
+            .any(|v| !v.as_os_str().is_empty() && self.globs.is_match(v))
+    }
+}
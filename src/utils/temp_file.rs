@@ -0,0 +1,105 @@
+use std::env;
+use std::fs;
+use std::fs::{File, OpenOptions};
+use std::io;
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::PathBuf;
+
+/// A file in the system's temporary folder that's deleted when it's dropped, for data too big to keep in memory
+pub struct TempFile {
+    file: Option<File>,
+    path: PathBuf,
+}
+
+impl TempFile {
+    pub fn new() -> io::Result<TempFile> {
+        let path = env::temp_dir().join(format!(
+            "minecraft-backup-manager-{:08x}.tmp",
+            rand::random::<u32>()
+        ));
+
+        let mut options = OpenOptions::new();
+        options.read(true).write(true).create_new(true);
+
+        // The contents of encrypted backups end up in here, so nobody else should be able to read it
+        #[cfg(unix)]
+        std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+
+        Ok(TempFile {
+            file: Some(options.open(&path)?),
+            path,
+        })
+    }
+
+    /// Copies everything in `data` into a new temporary file, ready to be read from the start
+    pub fn copy_from(data: &mut dyn Read) -> io::Result<TempFile> {
+        let mut file = TempFile::new()?;
+
+        io::copy(data, &mut file)?;
+        file.seek(SeekFrom::Start(0))?;
+
+        Ok(file)
+    }
+
+    fn file(&mut self) -> &mut File {
+        self.file.as_mut().unwrap()
+    }
+}
+
+impl Read for TempFile {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.file().read(buf)
+    }
+}
+
+impl Write for TempFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.file().write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file().flush()
+    }
+}
+
+impl Seek for TempFile {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        self.file().seek(pos)
+    }
+}
+
+impl Drop for TempFile {
+    fn drop(&mut self) {
+        // Windows can't delete files that are still open
+        drop(self.file.take());
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+/// A folder in the system's temporary folder that's deleted along with everything in it when it's dropped
+#[cfg(test)]
+pub struct TempDir(PathBuf);
+
+#[cfg(test)]
+impl TempDir {
+    pub fn new() -> TempDir {
+        let path = env::temp_dir().join(format!(
+            "minecraft-backup-manager-{:08x}",
+            rand::random::<u32>()
+        ));
+        fs::create_dir(&path).unwrap();
+
+        TempDir(path)
+    }
+
+    pub fn path(&self) -> &std::path::Path {
+        &self.0
+    }
+}
+
+#[cfg(test)]
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}